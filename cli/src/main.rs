@@ -2,18 +2,128 @@
 extern crate quicli;
 extern crate unit_splitter_core as core;
 
-use core::group::Groups;
+use core::group::{Groups, Name};
 use quicli::prelude::*;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, StructOpt)]
 struct Cli {
-    available_units: String,
+    /// How to choose which units go to each request: first-fit, best-fit, from-end or
+    /// round-robin
+    #[structopt(long = "strategy", short = "s", default_value = "first-fit")]
+    strategy: core::split::Strategy,
+    /// Give requests whatever units are available instead of stopping when a group runs
+    /// out, and list everything that is missing
+    #[structopt(long = "partial", short = "p")]
+    partial: bool,
+    /// Keep the units of each group in the order they were given out, instead of sorting
+    /// them and joining up ranges that are next to each other
+    #[structopt(long = "keep-order", short = "k")]
+    keep_order: bool,
+    /// Add together the amounts of requests that have the same name, instead of
+    /// reporting them as an error
+    #[structopt(long = "merge-duplicates", short = "m")]
+    merge_duplicates: bool,
+    /// Allow the same unit to be in more than one inventory group, and print a warning
+    /// for it instead of an error
+    #[structopt(long = "allow-shared-units")]
+    allow_shared_units: bool,
+    /// A ledger file of the units that have already been given out. Only units that
+    /// aren't in the ledger are split
+    #[structopt(long = "ledger", parse(from_os_str))]
+    ledger: Option<PathBuf>,
+    /// Record the split in the ledger, so that later splits don't give out the same
+    /// units again
+    #[structopt(long = "commit", requires = "ledger")]
+    commit: bool,
+    /// The units to split up, in the inventory notation
+    available_units: Option<String>,
+    /// The requests for units. Requests can be split over several arguments, or put
+    /// several to an argument. An argument like [htol] starts a new stage, which splits
+    /// up the units left over by the stages before it
     unit_requests: Vec<String>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Rewrite an inventory or requests in a canonical form: one group or request per
+    /// line, with sorted ranges and repeated amounts combined
+    #[structopt(name = "fmt")]
+    Fmt {
+        /// What is being formatted: inventory or requests
+        notation: Notation,
+        /// The file to format. Standard input is formatted if no file is given
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+        /// Write the formatted text back to the file instead of printing it
+        #[structopt(long = "write", short = "w")]
+        write: bool,
+    },
+    /// Give units held by a request back to the inventory, and record it in the ledger
+    #[structopt(name = "return")]
+    Return {
+        /// The name of the request giving the units back
+        request: String,
+        /// The units to give back, like 1-5 or LT8-LT12. Every unit the request holds
+        /// is given back if none are listed
+        units: Vec<String>,
+    },
+}
+
+#[derive(Debug)]
+enum Notation {
+    Inventory,
+    Requests,
+}
+
+impl Notation {
+    fn name(&self) -> &'static str {
+        match self {
+            Notation::Inventory => "inventory",
+            Notation::Requests => "requests",
+        }
+    }
+}
+
+impl std::str::FromStr for Notation {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "inventory" => Ok(Notation::Inventory),
+            "requests" => Ok(Notation::Requests),
+            _ => Err(format!("Expected inventory or requests, not \"{}\"", s)),
+        }
+    }
 }
 
 main!(|args: Cli| {
+    match args.command {
+        Some(Command::Fmt { notation, file, write }) => return format(&notation, file, write),
+        Some(Command::Return { request, units }) => return return_units(args.ledger, &request, &units),
+        None => {}
+    }
+    let available_units = match args.available_units {
+        Some(units) => units,
+        None => {
+            println!("No inventory given. Run with --help to see how to use the splitter.");
+            return Ok(());
+        }
+    };
+
     let inventory = {
-        let result = core::inventory::parse(&args.available_units);
+        let options = core::inventory::ParseOptions {
+            allow_shared_units: args.allow_shared_units,
+        };
+        let mut warnings = vec![];
+        let result = core::inventory::parse_with(&available_units, &options, &mut warnings);
+        for warning in warnings {
+            eprintln!("Warning: {}", warning);
+        }
         match result {
             Ok(r) => r,
             Err(errors) => {
@@ -25,8 +135,25 @@ main!(|args: Cli| {
             }
         }
     };
-    let requests = {
-        let result = core::requests::parse(&args.unit_requests.join(" "));
+    let mut ledger = match args.ledger {
+        Some(ref path) if path.exists() => match core::ledger::parse(&read_file(path)?) {
+            Ok(ledger) => ledger,
+            Err(errors) => {
+                println!("Error parsing ledger:");
+                for e in errors {
+                    println!("  {}", e);
+                }
+                return Ok(());
+            }
+        },
+        _ => core::ledger::Ledger::new(),
+    };
+    let inventory = ledger.available(&inventory);
+    let stages = {
+        let options = core::requests::ParseOptions {
+            merge_duplicates: args.merge_duplicates,
+        };
+        let result = core::pipeline::parse_with(&requests_text(&args.unit_requests), &options);
         match result {
             Ok(r) => r,
             Err(errors) => {
@@ -38,27 +165,235 @@ main!(|args: Cli| {
             }
         }
     };
-    let total_unit_count: u32 = inventory.iter().map(|g| g.count()).sum();
+    let total_unit_count = inventory.iter().fold(0u64, |total, g| total.saturating_add(g.count()));
     println!(
         "Dividing {} units between {} requests",
         total_unit_count,
-        requests.len()
+        stages.iter().map(|stage| stage.requests().len()).sum::<usize>()
     );
 
-    let split = core::split::split(&inventory, &requests);
-    match split {
-        Ok(core::split::Split {
-            filled_requests,
-            leftover_ranges,
-        }) => {
-            println!("");
-            for (request_name, inventory) in filled_requests.iter() {
-                println!("{}: {}", request_name, Groups(inventory));
+    let options = core::split::SplitOptions {
+        allocator: args.strategy.allocator(),
+        partial: args.partial,
+        normalize: !args.keep_order,
+    };
+    let pipeline = core::pipeline::split_with(&inventory, &stages, &options);
+    match pipeline {
+        Ok(pipeline) => {
+            for twice in pipeline.units_given_twice() {
+                eprintln!("Warning: {}", twice);
+            }
+            for ((stage_name, split), stage) in pipeline.stages.iter().zip(&stages) {
+                println!("");
+                if !stage_name.is_empty() {
+                    println!("[{}]", Name(stage_name));
+                }
+                print_filled_requests(split, stage.requests());
+            }
+            if pipeline.stages.len() > 1 {
+                println!();
+            }
+            println!("Leftover Units: {}", Groups(&pipeline.leftover_ranges));
+
+            for (stage_name, split) in pipeline.stages.iter().filter(|(_, split)| !split.shortfalls.is_empty()) {
+                println!("");
+                if stage_name.is_empty() {
+                    println!("Shortfalls:");
+                } else {
+                    println!("Shortfalls in [{}]:", Name(stage_name));
+                }
+                for shortfall in split.shortfalls.iter() {
+                    println!(
+                        "  {} is missing {} of {} units from {}",
+                        shortfall.request_name,
+                        shortfall.missing,
+                        shortfall.requested,
+                        shortfall.group_name
+                    );
+                }
+                for (group_name, missing) in split.missing_by_group() {
+                    println!("Total missing from {}: {}", group_name, missing);
+                }
+            }
+
+            if let (true, Some(path)) = (args.commit, args.ledger) {
+                let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                for (_, split) in &pipeline.stages {
+                    ledger.commit(split, timestamp);
+                }
+                write_to_file(&path, &ledger.to_string())?;
+                println!("");
+                println!("Committed the split to {}", path.display());
             }
-            println!("Leftover Units: {}", Groups(&leftover_ranges));
         }
-        Err(e) => {
-            println!("Error splitting units: {}", e);
+        Err(stage_errors) => {
+            if stage_errors.stage.is_empty() {
+                println!("Error splitting units:");
+            } else {
+                println!("Error splitting units in [{}]:", Name(&stage_errors.stage));
+            }
+            for e in stage_errors.errors {
+                println!("  {}", e);
+            }
         }
     }
 });
+
+/// Joins the request arguments into one requests text. Arguments are joined with
+/// spaces, so requests don't need to be quoted, like `A: 3 B: 2`. A stage header is
+/// put on a line of its own, and an argument with a comment ends its line, so that
+/// the comment doesn't swallow the arguments after it.
+///
+/// An argument like `[57]` is a stage header if the requests before it are complete,
+/// and otherwise it is read as pinned units, like in `A: [57]`.
+fn requests_text(args: &[String]) -> String {
+    let mut text = String::new();
+    for arg in args {
+        let line = text.rsplit('\n').next().unwrap_or("");
+        let is_header = core::pipeline::is_stage_header(arg)
+            && (line.trim().is_empty() || core::requests::parse(line).is_ok());
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push(if is_header { '\n' } else { ' ' });
+        }
+        text.push_str(arg);
+        if is_header || has_comment(arg) {
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// Whether `arg` has a `#` comment in it, outside of any quoted names
+fn has_comment(arg: &str) -> bool {
+    let mut in_quotes = false;
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '\\' if in_quotes => {
+                chars.next();
+            }
+            '#' if !in_quotes => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Prints the units given to each request of a split
+fn print_filled_requests(split: &core::split::Split, requests: &[core::request::Request]) {
+    // Filled requests are in the same order as the requests they came from
+    for ((request_name, inventory), request) in split.filled_requests.iter().zip(requests) {
+        let counts: Vec<String> = inventory.iter().map(|g| g.count().to_string()).collect();
+        match request.comment() {
+            Some(comment) => println!(
+                "{}: {} ({})  # {}",
+                Name(request_name),
+                Groups(inventory),
+                counts.join(", "),
+                comment
+            ),
+            None => println!("{}: {} ({})", Name(request_name), Groups(inventory), counts.join(", ")),
+        }
+    }
+}
+
+fn return_units(ledger_path: Option<PathBuf>, request: &str, units: &[String]) -> Result<()> {
+    let ledger_path = match ledger_path {
+        Some(path) => path,
+        None => {
+            println!("Units can only be returned to a ledger. Give one with --ledger.");
+            return Ok(());
+        }
+    };
+    let mut ledger = match core::ledger::parse(&read_file(&ledger_path)?) {
+        Ok(ledger) => ledger,
+        Err(errors) => {
+            println!("Error parsing ledger:");
+            for e in errors {
+                println!("  {}", e);
+            }
+            return Ok(());
+        }
+    };
+
+    let mut ranges = vec![];
+    for text in units.iter().flat_map(|u| u.split(',')).map(str::trim).filter(|u| !u.is_empty()) {
+        match text.parse::<core::range::Range>() {
+            Ok(range) => ranges.push(range),
+            Err(e) => {
+                println!("Error parsing units \"{}\": {}", text, e);
+                return Ok(());
+            }
+        }
+    }
+    let units = if ranges.is_empty() { None } else { Some(&ranges[..]) };
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    match ledger.return_units(request, units, timestamp) {
+        Ok(returned) => {
+            write_to_file(&ledger_path, &ledger.to_string())?;
+            println!("{} returned {}", Name(request), Groups(&returned));
+        }
+        Err(errors) => {
+            println!("Error returning units:");
+            for e in errors {
+                println!("  {}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn format(notation: &Notation, file: Option<PathBuf>, write: bool) -> Result<()> {
+    let input = match file {
+        Some(ref path) => read_file(path)?,
+        None => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            input
+        }
+    };
+    let result = match notation {
+        Notation::Inventory => core::format::format_inventory(&input)
+            .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>()),
+        Notation::Requests => core::format::format_requests(&input)
+            .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>()),
+    };
+    match (result, file) {
+        (Ok(formatted), Some(ref path)) if write => write_to_file(path, &formatted)?,
+        (Ok(formatted), _) => print!("{}", formatted),
+        (Err(errors), _) => {
+            println!("Error parsing {}:", notation.name());
+            for e in errors {
+                println!("  {}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::requests_text;
+
+    fn stages(args: &[&str]) -> Vec<core::pipeline::Stage> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        core::pipeline::parse(&requests_text(&args)).unwrap()
+    }
+
+    #[test]
+    fn several_arguments() {
+        let stages = stages(&["TC: 5", "B: 2 from TC", "C: 1", "X: same as C", "Y:", "3", "Z: 1 # spare", "W: 2"]);
+        let names: Vec<&str> = stages[0].requests().iter().map(|r| r.name()).collect();
+        assert_eq!(names, vec!["TC", "B", "C", "X", "Y", "Z", "W"]);
+    }
+
+    #[test]
+    fn stage_headers() {
+        let stages = stages(&["A:", "[57]", "[LT5]", "B: 3", "[57]", "C: 1"]);
+        let names: Vec<&str> = stages.iter().map(|stage| stage.name()).collect();
+        assert_eq!(names, vec!["", "LT5", "57"]);
+        assert_eq!(stages[0].requests()[0].amounts().len(), 1);
+    }
+}
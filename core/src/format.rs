@@ -0,0 +1,272 @@
+//! Rewrites inventory and requests text in a canonical form, so that the same
+//! inventory or requests are always written the same way.
+//!
+//! Formatting is idempotent: formatting text that has already been formatted gives
+//! back the same text. Comments are kept, both at the end of a group or request and
+//! on lines of their own, and so are single blank lines between them.
+
+use crate::group::{self, Name};
+use crate::inventory::{self, InventoryParseError, InventoryParser, Rule};
+use crate::pipeline::{self, PipelineParseError};
+use crate::range::{self, Range};
+use crate::request::{Amount, Quantity, Request};
+use crate::requests;
+use pest::iterators::Pair;
+use pest::Parser;
+use std::fmt::{self, Write};
+
+/// Formats an inventory with one group per line. The units listed in each group are
+/// sorted and merged where they touch. Expressions, references to other groups and
+/// excluded units are written out as they are, with one space around each operator.
+pub fn format_inventory(input: &str) -> Result<String, Vec<InventoryParseError>> {
+    let options = inventory::ParseOptions {
+        allow_shared_units: true,
+    };
+    inventory::parse_with(input, &options, &mut vec![])?;
+
+    let inventory = InventoryParser::parse(Rule::inventory, input)
+        .expect("the inventory was parsed above")
+        .next()
+        .unwrap();
+    let mut lines = Lines::default();
+    let mut end = 0;
+    for group in inventory.into_inner().filter(|pair| pair.as_rule() == Rule::group) {
+        lines.push_gap(&input[end..group.as_span().start()], end > 0);
+        end = inventory::last_token_end(&group);
+        let comment = inventory::trailing_comment(input, end);
+        lines.push(&format_group(group), comment.as_deref());
+    }
+    lines.push_gap(&input[end..], end > 0);
+    Ok(lines.output)
+}
+
+fn format_group(group: Pair<Rule>) -> String {
+    let mut output = String::new();
+    let mut pairs = group.into_inner().peekable();
+    if let Some(name) = pairs.next_if(|pair| pair.as_rule() == Rule::name) {
+        let _ = write!(output, "{}=", Name(&group::unquote(name.as_str())));
+    }
+    format_operands(pairs, &mut output);
+    output
+}
+
+/// Writes the operands and operators of an expression. Each run of units is sorted and
+/// merged, with the units excluded from it after a `!`.
+fn format_operands<'i>(pairs: impl Iterator<Item = Pair<'i, Rule>>, output: &mut String) {
+    let mut units: Vec<Range> = vec![];
+    for pair in pairs {
+        match pair.as_rule() {
+            Rule::union | Rule::intersection | Rule::difference => {
+                write_units(output, &mut units);
+                let _ = write!(output, " {} ", pair.as_str());
+            }
+            Rule::reference => {
+                let _ = write!(output, "{}", Reference(&group::unquote(pair.as_str())));
+            }
+            Rule::parenthesized => {
+                output.push('(');
+                format_operands(pair.into_inner(), output);
+                output.push(')');
+            }
+            Rule::exclusion => {
+                write_units(output, &mut units);
+                let mut excluded = parse_units(pair.into_inner());
+                output.push_str(" !");
+                write_units(output, &mut excluded);
+            }
+            _ => units.extend(parse_units(std::iter::once(pair))),
+        }
+    }
+    write_units(output, &mut units);
+}
+
+fn parse_units<'i>(pairs: impl Iterator<Item = Pair<'i, Rule>>) -> Vec<Range> {
+    pairs
+        .map(|pair| inventory::parse_ranges_from_rules(&pair, &mut vec![]).expect("the inventory was parsed above"))
+        .collect()
+}
+
+/// Writes `units` sorted and merged, and empties it
+fn write_units(output: &mut String, units: &mut Vec<Range>) {
+    for (idx, range) in range::normalize(units).iter().enumerate() {
+        if idx > 0 {
+            output.push_str(", ");
+        }
+        let _ = write!(output, "{}", range);
+    }
+    units.clear();
+}
+
+/// Displays a reference to a group. On top of the quoting done by `Name`, names that
+/// end in a digit are quoted, since they would otherwise be read as a unit.
+struct Reference<'a>(&'a str);
+
+impl<'a> fmt::Display for Reference<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = Name(self.0).to_string();
+        if name.ends_with(|c: char| c.is_ascii_digit()) {
+            group::write_quoted(f, self.0)
+        } else {
+            write!(f, "{}", name)
+        }
+    }
+}
+
+/// Formats requests with one request per line. Repeated amounts are written as a
+/// repetition (`32x3`), and pinned units are sorted and merged. Stage headers are kept
+/// on lines of their own.
+pub fn format_requests(input: &str) -> Result<String, Vec<PipelineParseError>> {
+    pipeline::parse(input)?;
+
+    let mut lines = Lines::default();
+    for line in input.lines() {
+        let trimmed = line
+            .trim_start_matches(|c: char| c.is_whitespace() || c == ',')
+            .trim_end();
+        if trimmed.is_empty() {
+            lines.blank = true;
+        } else if trimmed.starts_with('#') {
+            lines.push(&trimmed, None);
+        } else if let Some(name) = pipeline::stage_header(line) {
+            let comment = line.find(']').and_then(|end| inventory::trailing_comment(line, end + 1));
+            lines.push(&format!("[{}]", Name(&name)), comment.as_deref());
+        } else {
+            // Requests can't span lines, so each line can be parsed on its own
+            for request in requests::parse(line).expect("the requests were parsed above") {
+                let amounts = request.amounts().iter().map(normalize_amount).collect();
+                let mut normalized = Request::new(request.name().into(), amounts);
+                if let Some(source) = request.source() {
+                    normalized = normalized.with_source(source.clone());
+                }
+                lines.push(&normalized, request.comment());
+            }
+        }
+    }
+    Ok(lines.output)
+}
+
+fn normalize_amount(amount: &Amount) -> Amount {
+    match amount.quantity() {
+        Quantity::Units(ranges) => Amount::with_quantity(
+            amount.group().map(String::from),
+            Quantity::Units(range::normalize(ranges)),
+        ),
+        _ => amount.clone(),
+    }
+}
+
+/// The formatted text, one line at a time. Blank lines are only written between two
+/// other lines, and never more than one in a row.
+#[derive(Default)]
+struct Lines {
+    output: String,
+    /// Whether a blank line should come before the next line
+    blank: bool,
+}
+
+impl Lines {
+    fn push<T: fmt::Display>(&mut self, item: &T, comment: Option<&str>) {
+        if self.blank && !self.output.is_empty() {
+            self.output.push('\n');
+        }
+        self.blank = false;
+        let _ = write!(self.output, "{}", item);
+        match comment {
+            Some("") => self.output.push_str("  #"),
+            Some(comment) => {
+                let _ = write!(self.output, "  # {}", comment);
+            }
+            None => {}
+        }
+        self.output.push('\n');
+    }
+
+    /// Keeps the comment lines and blank lines in `gap`, which is the text between two
+    /// groups. If `gap` comes after a group, its first line is the rest of that group's
+    /// line, which holds the group's own comment.
+    fn push_gap(&mut self, gap: &str, after_group: bool) {
+        let segments: Vec<&str> = gap.split('\n').collect();
+        for (idx, segment) in segments.iter().enumerate() {
+            if idx == 0 && after_group {
+                continue;
+            }
+            let trimmed = segment.trim_matches(|c: char| c.is_whitespace() || c == ',');
+            if trimmed.starts_with('#') {
+                self.push(&trimmed, None);
+            } else if trimmed.is_empty() && idx + 1 < segments.len() && (idx > 0 || !after_group) {
+                // The last segment is the start of the next group's line, not a line
+                self.blank = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::{format_inventory, format_requests};
+
+    #[test]
+    fn inventory() {
+        let input = "EVAL=60-72, 57-58,74 ,73 !60\n  CTRL = 21-30 31-40 # lot 7\n\"HTOL 125C\"=LT8, LT9-LT12";
+        assert_eq!(
+            format_inventory(input).unwrap(),
+            "EVAL=57-58, 60-74 !60\nCTRL=21-40  # lot 7\n\"HTOL 125C\"=LT8-LT12\n"
+        );
+    }
+
+    #[test]
+    fn inventory_expressions() {
+        let input = "ALL=1-100\nFAILED=9,5\nGOOD=ALL  -FAILED\nEVAL = GOOD&(1-50 !7 |\"LOT7\")\nLOT7=101-110";
+        assert_eq!(
+            format_inventory(input).unwrap(),
+            "ALL=1-100\nFAILED=5, 9\nGOOD=ALL - FAILED\nEVAL=GOOD & (1-50 !7 | \"LOT7\")\nLOT7=101-110\n"
+        );
+    }
+
+    #[test]
+    fn comment_lines() {
+        let input = "\n# Week 12\n\n\nA=1-10 # lot 7\n  # retest\nB=11-20, C=21-30\n\n# end";
+        assert_eq!(
+            format_inventory(input).unwrap(),
+            "# Week 12\n\nA=1-10  # lot 7\n# retest\nB=11-20\nC=21-30\n\n# end\n"
+        );
+
+        let input = "# Week 12\nA: 1,1\n\n[htol]  # 125C\n\n\n# first\nB: 2 C: 3 # for Bob\n";
+        assert_eq!(
+            format_requests(input).unwrap(),
+            "# Week 12\nA: 1x2\n\n[htol]  # 125C\n\n# first\nB: 2\nC: 3  # for Bob\n"
+        );
+    }
+
+    #[test]
+    fn requests() {
+        let input = "A: 32,32 , 32, CTRL=[25, 21-24]  #for Alice\n\"B: 1\" : 1/4, 1/4, *,*, -, -\nC D: 5%x2, 5%";
+        assert_eq!(
+            format_requests(input).unwrap(),
+            "A: 32x3, CTRL=[21-25]  # for Alice\n\"B: 1\": 1/4x2, *x2, -, -\nC D: 5%x3\n"
+        );
+    }
+
+    #[test]
+    fn idempotent() {
+        let inventories = [
+            "1-5, 6-10, A=007-012 !9 #\nB=LT123-0057-0072, LT123-0001",
+            "\"5\\\" WAFER\"=1-10, 11-17",
+            "# x\n\nA=1-99/2\nB = A | (200-300 !250) - \"C 1\"\n\"C 1\"=1",
+        ];
+        for input in &inventories {
+            let formatted = format_inventory(input).unwrap();
+            assert_eq!(format_inventory(&formatted).unwrap(), formatted);
+        }
+        let requests = [
+            "A: 1,1, EVAL=2, 50%x2, [3, 1-2] # x\nB C: -, *, 1/3",
+            "\"A\\\\B\": LT8-LT9",
+            "TC: 77\nHAST: same as TC\n\"#2\": 5x2 from HAST",
+            "[\"stage 1\"]\nA: 1\n# x\n\n[b]\nA: 2",
+        ];
+        for input in &requests {
+            let formatted = format_requests(input).unwrap();
+            assert_eq!(format_requests(&formatted).unwrap(), formatted);
+        }
+    }
+}
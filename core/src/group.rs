@@ -1,24 +1,80 @@
-use crate::range::Range;
+use crate::range::{self, Range};
+use crate::range_set::RangeSet;
 use std::fmt;
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct Group {
     name: String,
+    /// The units of the group, in the order they are given out in
     ranges: Vec<Range>,
+    units: RangeSet,
+    comment: Option<String>,
 }
 
 impl Group {
     pub fn new(name: String, ranges: Vec<Range>) -> Self {
-        Self { name, ranges }
+        Self {
+            name,
+            units: RangeSet::from(&ranges[..]),
+            ranges,
+            comment: None,
+        }
+    }
+
+    /// Attaches a comment to the group, like the `# lot 7` at the end of
+    /// `EVAL=1-50 # lot 7`
+    pub fn with_comment(self, comment: String) -> Self {
+        Self {
+            comment: Some(comment),
+            ..self
+        }
     }
 
+    /// A group with the same name and comment as this one, but different units
     pub fn with_ranges(&self, ranges: Vec<Range>) -> Self {
         Self {
             name: self.name.clone(),
+            units: RangeSet::from(&ranges[..]),
             ranges,
+            comment: self.comment.clone(),
         }
     }
 
+    /// A group with the same name and comment as this one, but with `units` as its units,
+    /// in sorted order
+    pub fn with_units(&self, units: RangeSet) -> Self {
+        Self {
+            name: self.name.clone(),
+            ranges: units.ranges().to_vec(),
+            units,
+            comment: self.comment.clone(),
+        }
+    }
+
+    /// The same group without any of the units in `units`. The units that are left keep
+    /// the order they had in this group.
+    pub fn without(&self, units: &RangeSet) -> Self {
+        Self {
+            name: self.name.clone(),
+            ranges: range::subtract(&self.ranges, units),
+            units: &self.units - units,
+            comment: self.comment.clone(),
+        }
+    }
+
+    /// The same group with its ranges sorted, and any ranges that overlap or are next
+    /// to each other merged, so `6-10, 1-5, 12` becomes `1-10, 12`. Ranges with a prefix
+    /// are sorted by prefix first.
+    pub fn normalized(&self) -> Self {
+        self.with_units(self.units.clone())
+    }
+
+    /// The units in the group as a set, for working out which units two groups have in
+    /// common and the like
+    pub fn units(&self) -> &RangeSet {
+        &self.units
+    }
+
     pub fn name(&self) -> &str {
         self.name.as_str()
     }
@@ -27,15 +83,20 @@ impl Group {
         &self.ranges
     }
 
-    pub fn count(&self) -> u32 {
-        self.ranges.iter().map(|r| r.count()).sum()
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// The number of units in the group, or `u64::MAX` if there are more than that
+    pub fn count(&self) -> u64 {
+        self.units.len()
     }
 }
 
 impl fmt::Display for Group {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.name != "" {
-            write!(f, "{}=", self.name)?;
+            write!(f, "{}=", Name(&self.name))?;
         }
         let mut need_comma = false;
         for range in &self.ranges {
@@ -67,6 +128,52 @@ impl<'a> fmt::Display for Groups<'a> {
     }
 }
 
+/// Displays a group or request name so that it can be read back in. Names with spaces
+/// or characters that mean something in the notation are put in quotes, like
+/// `"HTOL 125C"`, with any quotes or backslashes in them escaped.
+pub struct Name<'a>(pub &'a str);
+
+impl<'a> fmt::Display for Name<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let needs_quotes =
+            self.0.is_empty() || self.0.chars().any(|c| c.is_whitespace() || ",=:!#\"\\()&|".contains(c));
+        if needs_quotes {
+            write_quoted(f, self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+/// Writes `name` in quotes, escaping any quotes or backslashes in it
+pub(crate) fn write_quoted(f: &mut fmt::Formatter, name: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in name.chars() {
+        if c == '"' || c == '\\' {
+            write!(f, "\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    write!(f, "\"")
+}
+
+/// Reads a name that may be in quotes, taking out the quotes and escapes
+pub(crate) fn unquote(text: &str) -> String {
+    if text.len() < 2 || !text.starts_with('"') || !text.ends_with('"') {
+        return text.into();
+    }
+    let mut name = String::new();
+    let mut chars = text[1..text.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            name.extend(chars.next());
+        } else {
+            name.push(c);
+        }
+    }
+    name
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,4 +205,39 @@ mod tests {
         ];
         assert_eq!(format!("{}", Groups(&groups)), "A=1-50, 61, CTRL=1-50, 61");
     }
+
+    #[test]
+    fn normalized() {
+        let group = Group::new("A".into(), vec![Range::new(68, 72), Range::num(73), Range::new(1, 5)])
+            .with_comment("lot 7".into());
+        assert_eq!(
+            group.normalized(),
+            Group::new("A".into(), vec![Range::new(1, 5), Range::new(68, 73)]).with_comment("lot 7".into())
+        );
+    }
+
+    #[test]
+    fn without_units() {
+        let group = Group::new("A".into(), vec![Range::new(68, 72), Range::new(1, 5)]);
+        let group = group.without(&RangeSet::from(Range::new(3, 70)));
+        assert_eq!(group.ranges(), &[Range::new(71, 72), Range::new(1, 2)]);
+        assert_eq!(group.units().ranges(), &[Range::new(1, 2), Range::new(71, 72)]);
+        assert_eq!(group.count(), 4);
+        assert_eq!(group.with_units(group.units().clone()), group.normalized());
+    }
+
+    #[test]
+    fn format_quoted_name() {
+        let group = Group::new("HTOL 125C".into(), vec![Range::new(1, 50)]);
+        assert_eq!(format!("{}", group), "\"HTOL 125C\"=1-50");
+        let group = Group::new("5\" \\ WAFER".into(), vec![Range::num(7)]);
+        assert_eq!(format!("{}", group), "\"5\\\" \\\\ WAFER\"=7");
+    }
+
+    #[test]
+    fn unquote_name() {
+        assert_eq!(unquote("\"HTOL 125C\""), "HTOL 125C");
+        assert_eq!(unquote("\"5\\\" \\\\ WAFER\""), "5\" \\ WAFER");
+        assert_eq!(unquote("EVAL"), "EVAL");
+    }
 }
@@ -1,8 +1,10 @@
-use crate::group::Group;
+use crate::group::{self, Group};
 use crate::interval_tree::IntervalTree;
-use crate::range::Range;
+use crate::range::{self, Range, RangeParseError};
+use crate::range_set::RangeSet;
+use pest::iterators::Pair;
 use pest::Parser;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Parser)]
 #[grammar = "inventory.pest"]
@@ -11,25 +13,39 @@ pub struct InventoryParser;
 pub type InventoryParseResult = Result<Vec<Group>, Vec<InventoryParseError>>;
 
 pub fn parse(input: &str) -> InventoryParseResult {
+    parse_with(input, &ParseOptions::default(), &mut vec![])
+}
+
+/// Parses an inventory with the given options. Problems that `options` allow, such as
+/// units that are in more than one group, are appended to `warnings` instead of being
+/// returned as errors.
+pub fn parse_with(
+    input: &str,
+    options: &ParseOptions,
+    warnings: &mut Vec<InventoryParseError>,
+) -> InventoryParseResult {
     let mut parse = InventoryParser::parse(Rule::inventory, input).map_err(|x| vec![x.into()])?;
     let inventory = parse
         .next()
         .expect("If there is no input, SyntaxError is returned in the above statement");
-    let mut groups = vec![];
+    let mut definitions = vec![];
+    let mut group_idxs = HashMap::new();
     let mut group_spans: HashMap<_, ::pest::Span> = HashMap::new();
     let mut errors = vec![];
+
+    // Every group is read in before any are worked out, so that a group can refer to
+    // groups that come after it
     for group in inventory.into_inner() {
         match group.as_rule() {
             Rule::group => {
-                let mut inner = group.into_inner();
-                let first = inner.next().unwrap();
-
-                let mut ranges = vec![];
-                let mut interval_tree = IntervalTree::new();
+                let start = group.as_span().start();
+                let end = last_token_end(&group);
+                let mut body: Vec<_> = group.into_inner().collect();
 
                 let name;
-                if first.as_rule() == Rule::name {
-                    name = String::from(first.as_str());
+                if body[0].as_rule() == Rule::name {
+                    let first = body.remove(0);
+                    name = group::unquote(first.as_str());
 
                     // Test if another group with this name has been defined
                     if group_spans.contains_key(&name) {
@@ -41,57 +57,83 @@ pub fn parse(input: &str) -> InventoryParseResult {
                         };
                         errors.push(err);
                     } else {
+                        group_idxs.insert(name.clone(), definitions.len());
                         group_spans.insert(name.clone(), first.as_span());
                     }
                 } else {
                     // Default to an empty name if no name is given for the group
                     name = String::new();
-
-                    // Since we can't put `first` back onto the iterator, we'll have
-                    // to go ahead and parse the range here instead of in the main
-                    // loop.
-                    //
-                    // We don't if it is indeed a range, because `inventory.pest`
-                    // ensures that if this isn't a name, it's a range.
-                    match parse_ranges_from_rules(&first, &mut errors) {
-                        Ok(range) => {
-                            interval_tree.insert(range.clone(), first.as_span());
-                            ranges.push(range);
-                        }
-                        Err(()) => continue,
-                    };
                 }
 
-                // Read all the ranges in
-                for pair in inner {
-                    // The next rules can only be ranges
-                    let range = match parse_ranges_from_rules(&pair, &mut errors) {
-                        Ok(r) => r,
-                        Err(()) => continue,
-                    };
-
-                    // Test to make sure that no unit numbers have been duplicated.
-                    let mut overlaps = vec![];
-                    interval_tree.overlap_search(&range, &mut overlaps);
-                    for (_overlapping_range, overlapping_span) in overlaps {
-                        let err = InventoryParseError::OverlappingRange {
-                            first: (&overlapping_span).into(),
-                            overlap: (&pair.as_span()).into(),
+                definitions.push(Definition {
+                    name,
+                    body,
+                    start,
+                    end,
+                });
+            }
+            Rule::EOI => {}
+            _ => unreachable!(),
+        }
+    }
+
+    // The groups each group is worked out from, for finding units that are in more
+    // than one group
+    let lineages: Vec<HashSet<usize>> = (0..definitions.len())
+        .map(|idx| lineage(&definitions, &group_idxs, idx))
+        .collect();
+    let mut evaluator = Evaluator {
+        definitions: &definitions,
+        group_idxs,
+        results: vec![None; definitions.len()],
+        in_progress: vec![false; definitions.len()],
+        errors: &mut errors,
+    };
+    let mut groups = vec![];
+    // The units of each group that lists its own units, for finding units that are in
+    // more than one group. Groups that refer to other groups are left out, since they
+    // are meant to share units with the groups they refer to. Two groups that a third
+    // group is worked out from may also share units, like `ALL` and `FAILED` in
+    // `GOOD = ALL - FAILED`, but they are still checked against every other group.
+    let mut claimed: Vec<(usize, RangeSet, OwnedSpan)> = vec![];
+    for (idx, definition) in definitions.iter().enumerate() {
+        if let Ok(ranges) = evaluator.group(idx) {
+            if definition.references().next().is_none() {
+                let units = RangeSet::from(&ranges[..]);
+                let span = OwnedSpan::new(
+                    definition.start,
+                    definition.end,
+                    input[definition.start..definition.end].into(),
+                );
+                for (other_idx, other_units, other_span) in &claimed {
+                    let related = lineages
+                        .iter()
+                        .any(|lineage| lineage.contains(&idx) && lineage.contains(other_idx));
+                    if related {
+                        continue;
+                    }
+                    let shared = other_units & &units;
+                    if !shared.is_empty() {
+                        let err = InventoryParseError::SharedUnits {
+                            first: other_span.clone(),
+                            overlap: span.clone(),
+                            units: shared,
                         };
-                        errors.push(err);
-                        // We don't `continue` here so that all numbers that have
-                        // been duplicated can be caught at once.
+                        if options.allow_shared_units {
+                            warnings.push(err);
+                        } else {
+                            evaluator.errors.push(err);
+                        }
                     }
-
-                    interval_tree.insert(range.clone(), pair.as_span());
-
-                    ranges.push(range);
                 }
-
-                groups.push(Group::new(name, ranges));
+                claimed.push((idx, units, span));
             }
-            Rule::EOI => {}
-            _ => unreachable!(),
+
+            let group = Group::new(definition.name.clone(), ranges);
+            groups.push(match trailing_comment(input, definition.end) {
+                Some(comment) => group.with_comment(comment),
+                None => group,
+            });
         }
     }
 
@@ -102,6 +144,13 @@ pub fn parse(input: &str) -> InventoryParseResult {
     }
 }
 
+#[derive(Default)]
+pub struct ParseOptions {
+    /// Allow the same unit to be in more than one group. Units that are shared are
+    /// reported as warnings instead of errors.
+    pub allow_shared_units: bool,
+}
+
 #[derive(Fail, Debug, PartialEq)]
 pub enum InventoryParseError {
     #[fail(display = "Invalid syntax:\n{}", _0)]
@@ -130,6 +179,28 @@ pub enum InventoryParseError {
 
     #[fail(display = "Number is too large to parse: {:?}", number)]
     NumberTooLarge { number: OwnedSpan },
+
+    #[fail(display = "Step must be at least 1: {:?}", step)]
+    ZeroStep { step: OwnedSpan },
+
+    #[fail(display = "Excluded units are not in the group: {:?}", excluded)]
+    ExcludedUnitsNotInGroup { excluded: OwnedSpan },
+
+    #[fail(display = "Group is not defined: {:?}", reference)]
+    UndefinedGroup { reference: OwnedSpan },
+
+    #[fail(display = "Group is defined in terms of itself: {:?}", reference)]
+    CyclicGroup { reference: OwnedSpan },
+
+    #[fail(
+        display = "Units are in more than one group: {} in {:?} are also in {:?}",
+        units, overlap, first
+    )]
+    SharedUnits {
+        first: OwnedSpan,
+        overlap: OwnedSpan,
+        units: RangeSet,
+    },
 }
 
 impl From<::pest::error::Error<Rule>> for InventoryParseError {
@@ -177,18 +248,259 @@ impl From<&'_ ::pest::Span<'_>> for OwnedSpan {
     }
 }
 
-/// Parses a Pair that is of `Rule::range` or `Rule::number` into a Range, or returns
-/// an error if Range decreases in value or is larger than the maximum size of a u32.
-/// Errors are appended to `errors`, and any error will cause `Err` to be returned.
-fn parse_ranges_from_rules(
+/// A group as it was written, before its units have been worked out
+struct Definition<'i> {
+    name: String,
+    /// Everything after the name: lists of units, references to other groups and
+    /// the operators between them
+    body: Vec<Pair<'i, Rule>>,
+    /// Where the group starts, including its name
+    start: usize,
+    /// Where the last token of the group ends
+    end: usize,
+}
+
+impl<'i> Definition<'i> {
+    /// The names of the groups that the group refers to
+    fn references(&self) -> impl Iterator<Item = String> + '_ {
+        self.body
+            .iter()
+            .flat_map(|pair| pair.clone().into_inner().flatten().chain(Some(pair.clone())))
+            .filter(|pair| pair.as_rule() == Rule::reference)
+            .map(|pair| group::unquote(pair.as_str()))
+    }
+}
+
+/// The group at `idx` and all the groups it is worked out from, directly or through
+/// other groups
+fn lineage(definitions: &[Definition], group_idxs: &HashMap<String, usize>, idx: usize) -> HashSet<usize> {
+    let mut lineage = HashSet::new();
+    let mut pending = vec![idx];
+    while let Some(idx) = pending.pop() {
+        if lineage.insert(idx) {
+            pending.extend(definitions[idx].references().filter_map(|name| group_idxs.get(&name).cloned()));
+        }
+    }
+    lineage
+}
+
+/// Works out the units in each group, following references to other groups as needed.
+/// Each group is only worked out once.
+struct Evaluator<'a, 'i> {
+    definitions: &'a [Definition<'i>],
+    group_idxs: HashMap<String, usize>,
+    /// The units of each group that has been worked out so far, or `Err` if the group
+    /// has an error that has already been reported
+    results: Vec<Option<Result<Vec<Range>, ()>>>,
+    /// The groups that are being worked out, for catching groups that refer to
+    /// themselves
+    in_progress: Vec<bool>,
+    errors: &'a mut Vec<InventoryParseError>,
+}
+
+impl<'a, 'i> Evaluator<'a, 'i> {
+    fn group(&mut self, idx: usize) -> Result<Vec<Range>, ()> {
+        if let Some(result) = &self.results[idx] {
+            return result.clone();
+        }
+        let definitions = self.definitions;
+        self.in_progress[idx] = true;
+        let result = self.expression(&definitions[idx].body);
+        self.in_progress[idx] = false;
+        self.results[idx] = Some(result.clone());
+        result
+    }
+
+    fn reference(&mut self, reference: &Pair<Rule>) -> Result<Vec<Range>, ()> {
+        let name = group::unquote(reference.as_str());
+        match self.group_idxs.get(&name) {
+            Some(&idx) if self.in_progress[idx] => {
+                self.errors.push(InventoryParseError::CyclicGroup {
+                    reference: (&reference.as_span()).into(),
+                });
+                Err(())
+            }
+            Some(&idx) => self.group(idx),
+            None => {
+                self.errors.push(InventoryParseError::UndefinedGroup {
+                    reference: (&reference.as_span()).into(),
+                });
+                Err(())
+            }
+        }
+    }
+
+    /// Works out operands separated by operators, from left to right. An expression
+    /// that is just a list of units keeps the units in the order they were written in;
+    /// otherwise the units are sorted.
+    fn expression(&mut self, pairs: &[Pair<'i, Rule>]) -> Result<Vec<Range>, ()> {
+        let mut operands = vec![];
+        let mut operator = None;
+        let mut idx = 0;
+        while idx < pairs.len() {
+            let pair = &pairs[idx];
+            idx += 1;
+            let units = match pair.as_rule() {
+                Rule::union | Rule::intersection | Rule::difference => {
+                    operator = Some(pair.as_rule());
+                    continue;
+                }
+                Rule::reference => self.reference(pair),
+                Rule::parenthesized => {
+                    let inner: Vec<_> = pair.clone().into_inner().collect();
+                    self.expression(&inner)
+                }
+                _ => {
+                    // A list of units runs up to the next operator
+                    let start = idx - 1;
+                    while idx < pairs.len() && is_unit_rule(pairs[idx].as_rule()) {
+                        idx += 1;
+                    }
+                    Ok(parse_units(&pairs[start..idx], self.errors))
+                }
+            };
+            operands.push((operator.take(), units));
+        }
+
+        if operands.len() == 1 {
+            return operands.pop().unwrap().1;
+        }
+        let mut result = Ok(RangeSet::new());
+        for (operator, units) in operands {
+            result = match (result, units) {
+                (Ok(set), Ok(units)) => {
+                    let units = RangeSet::from(&units[..]);
+                    Ok(match operator {
+                        Some(Rule::union) => set | units,
+                        Some(Rule::intersection) => set & units,
+                        Some(Rule::difference) => set - units,
+                        _ => units,
+                    })
+                }
+                _ => Err(()),
+            };
+        }
+        result.map(RangeSet::into_ranges)
+    }
+}
+
+fn is_unit_rule(rule: Rule) -> bool {
+    matches!(
+        rule,
+        Rule::stepped | Rule::serial | Rule::range | Rule::number | Rule::exclusion
+    )
+}
+
+/// Parses a list of units, like `1-50 51 !7`. Errors are appended to `errors`, and the
+/// units that could be parsed are returned.
+fn parse_units(pairs: &[Pair<Rule>], errors: &mut Vec<InventoryParseError>) -> Vec<Range> {
+    let mut ranges = vec![];
+    let mut interval_tree: IntervalTree<::pest::Span> = IntervalTree::new();
+    for pair in pairs {
+        if pair.as_rule() == Rule::exclusion {
+            ranges = exclude_ranges(ranges, pair, errors);
+            continue;
+        }
+
+        // The other rules can only be ranges
+        let range = match parse_ranges_from_rules(pair, errors) {
+            Ok(r) => r,
+            Err(()) => continue,
+        };
+
+        // Test to make sure that no unit numbers have been duplicated.
+        let mut overlaps = vec![];
+        interval_tree.overlap_search(&range, &mut overlaps);
+        for (_overlapping_range, overlapping_span) in overlaps {
+            let err = InventoryParseError::OverlappingRange {
+                first: (&overlapping_span).into(),
+                overlap: (&pair.as_span()).into(),
+            };
+            errors.push(err);
+            // We don't `continue` here so that all numbers that have
+            // been duplicated can be caught at once.
+        }
+
+        interval_tree.insert(range.clone(), pair.as_span());
+
+        ranges.push(range);
+    }
+    ranges
+}
+
+/// Where the last token inside `pair` ends. The span of `pair` itself can run past that
+/// and into any whitespace or comments that follow it.
+pub(crate) fn last_token_end(pair: &Pair<Rule>) -> usize {
+    match pair.clone().into_inner().last() {
+        // The closing parenthesis isn't a pair of its own, but it ends the span
+        _ if pair.as_rule() == Rule::parenthesized => pair.as_span().end(),
+        Some(last) => last_token_end(&last),
+        None => pair.as_span().end(),
+    }
+}
+
+/// Finds the comment on the same line as `end`, if nothing but whitespace comes between
+/// them. Used to attach a trailing comment to whatever ends at `end`.
+pub(crate) fn trailing_comment(input: &str, end: usize) -> Option<String> {
+    let rest = input[end..].trim_start_matches(&[' ', '\t', ','][..]);
+    if !rest.starts_with('#') {
+        return None;
+    }
+    let line = rest[1..].lines().next().unwrap_or("");
+    Some(line.trim().into())
+}
+
+/// Removes the units listed in a `Rule::exclusion` pair from `ranges`. Any excluded
+/// units that aren't in `ranges` are reported as an error.
+fn exclude_ranges(
+    ranges: Vec<Range>,
+    exclusion: &::pest::iterators::Pair<Rule>,
+    errors: &mut Vec<InventoryParseError>,
+) -> Vec<Range> {
+    let group_units = RangeSet::from(&ranges[..]);
+    let mut excluded_units = RangeSet::new();
+    for pair in exclusion.clone().into_inner() {
+        let excluded = match parse_ranges_from_rules(&pair, errors) {
+            Ok(r) => RangeSet::from(r),
+            Err(()) => continue,
+        };
+
+        if !excluded.is_subset(&group_units) {
+            errors.push(InventoryParseError::ExcludedUnitsNotInGroup {
+                excluded: (&pair.as_span()).into(),
+            });
+        }
+
+        excluded_units = excluded_units | excluded;
+    }
+    range::subtract(&ranges, &excluded_units)
+}
+
+/// Parses a Pair that is of `Rule::stepped`, `Rule::serial`, `Rule::range` or `Rule::number` into a Range,
+/// or returns an error if Range decreases in value or is larger than `range::MAX_UNIT`. Errors are appended to `errors`, and any error will cause `Err` to be returned.
+pub(crate) fn parse_ranges_from_rules(
     pair: &::pest::iterators::Pair<Rule>,
     errors: &mut Vec<InventoryParseError>,
 ) -> Result<Range, ()> {
     match pair.as_rule() {
         Rule::number => {
             let num = parse_number_from_pair(pair, errors)?;
-            return Ok(Range::num(num));
+            let width = range::padded_width(pair.as_str());
+            return Ok(Range::with_prefix(String::new(), num, num, width));
         }
+        Rule::serial => match pair.as_str().parse() {
+            Ok(range) => return Ok(range),
+            Err(RangeParseError::NumberTooLarge) => {
+                errors.push(InventoryParseError::NumberTooLarge {
+                    number: (&pair.as_span()).into(),
+                });
+            }
+            Err(_) => {
+                errors.push(InventoryParseError::DecreasingRange {
+                    range: (&pair.as_span()).into(),
+                });
+            }
+        },
         Rule::range => {
             let mut inner = pair.clone().into_inner();
             let first_pair = inner.next().expect("Rule::range must have two numbers");
@@ -198,7 +510,10 @@ fn parse_ranges_from_rules(
             let last_res = parse_number_from_pair(&last_pair, errors);
 
             match (first_res, last_res) {
-                (Ok(first), Ok(last)) if first <= last => return Ok(Range::new(first, last)),
+                (Ok(first), Ok(last)) if first <= last => {
+                    let width = range::padded_width(first_pair.as_str());
+                    return Ok(Range::with_prefix(String::new(), first, last, width));
+                }
                 _ => {
                     errors.push(InventoryParseError::DecreasingRange {
                         range: (&pair.as_span()).into(),
@@ -206,19 +521,37 @@ fn parse_ranges_from_rules(
                 }
             }
         }
+        Rule::stepped => {
+            let mut inner = pair.clone().into_inner();
+            let range_pair = inner.next().expect("Rule::stepped must have a range");
+            let step_pair = inner.next().expect("Rule::stepped must have a step");
+
+            let range_res = parse_ranges_from_rules(&range_pair, errors);
+            let step_res = parse_number_from_pair(&step_pair, errors);
+
+            match (range_res, step_res) {
+                (Ok(range), Ok(step)) if step > 0 => return Ok(range.with_step(step)),
+                (_, Ok(0)) => {
+                    errors.push(InventoryParseError::ZeroStep {
+                        step: (&step_pair.as_span()).into(),
+                    });
+                }
+                _ => {}
+            }
+        }
         _ => unreachable!(),
     }
     Err(())
 }
 
 /// Parses a pair as a number. This should only be called on pairs you know to be a number!
-/// Any parsing error is attributed to the number being to large to fit in a u32, as all other
-/// errors should be impossible after the `inventory.pest` parser has confirmed it.
+/// Any parsing error is attributed to the number being larger than `range::MAX_UNIT`, as all
+/// other errors should be impossible after the `inventory.pest` parser has confirmed it.
 fn parse_number_from_pair(
     pair: &::pest::iterators::Pair<Rule>,
     errors: &mut Vec<InventoryParseError>,
-) -> Result<u32, ()> {
-    match pair.as_str().parse() {
+) -> Result<u64, ()> {
+    match range::parse_number(pair.as_str()) {
         Ok(num) => Ok(num),
         Err(_) => {
             errors.push(InventoryParseError::NumberTooLarge {
@@ -232,8 +565,11 @@ fn parse_number_from_pair(
 #[cfg(test)]
 mod tests {
     use crate::group::Group;
-    use crate::inventory::{parse, InventoryParseError, InventoryParser, OwnedSpan, Rule};
-    use crate::range::Range;
+    use crate::inventory::{
+        last_token_end, parse, parse_with, InventoryParseError, InventoryParser, OwnedSpan, ParseOptions, Rule,
+    };
+    use crate::range::{self, Range};
+    use pest::Parser;
 
     #[test]
     fn one_group() {
@@ -448,4 +784,405 @@ mod tests {
             }])
         );
     }
+
+    #[test]
+    fn exclusion() {
+        let input = "A=1-50 !7,9-10 B=51";
+        let inventory = InventoryParser::parse(Rule::inventory, input).unwrap().next().unwrap();
+        let groups: Vec<_> = inventory.into_inner().filter(|pair| pair.as_rule() == Rule::group).collect();
+        assert_eq!(groups.len(), 2);
+        // The span of a group can take in the whitespace after it, depending on the
+        // version of pest, so only check where its last token ends
+        assert_eq!(&input[..last_token_end(&groups[0])], "A=1-50 !7,9-10");
+
+        let rules: Vec<_> = groups[0].clone().into_inner().map(|pair| pair.as_rule()).collect();
+        assert_eq!(rules, vec![Rule::name, Rule::range, Rule::exclusion]);
+        let exclusion = groups[0].clone().into_inner().last().unwrap();
+        let excluded: Vec<_> = exclusion.into_inner().map(|pair| (pair.as_rule(), pair.as_str())).collect();
+        assert_eq!(excluded, vec![(Rule::number, "7"), (Rule::range, "9-10")]);
+    }
+
+    #[test]
+    fn excluded_units() {
+        let result = parse("EVAL=57-113 !60,74,94-98");
+        let expected = vec![Group::new(
+            "EVAL".to_string(),
+            vec![
+                Range::new(57, 59),
+                Range::new(61, 73),
+                Range::new(75, 93),
+                Range::new(99, 113),
+            ],
+        )];
+
+        assert_eq!(result, Ok(expected));
+    }
+
+    #[test]
+    fn excluded_units_not_in_group() {
+        let result = parse("EVAL=1-10 !5, 9-12");
+        assert_eq!(
+            result,
+            Err(vec![InventoryParseError::ExcludedUnitsNotInGroup {
+                excluded: OwnedSpan::new(14, 18, "9-12".into()),
+            }])
+        );
+    }
+
+    #[test]
+    fn serial_numbers() {
+        parses_to! {
+            parser: InventoryParser,
+            input: "A=LT123-0057-0072, 0007",
+            rule: Rule::inventory,
+            tokens: [
+                inventory(0, 23, [
+                    group(0, 23, [
+                        name(0, 1),
+                        serial(2, 17),
+                        number(19, 23)
+                    ]),
+                    EOI(23, 23)
+                ])
+            ]
+        };
+    }
+
+    #[test]
+    fn serial_numbers_to_data() {
+        let result = parse("A=LT123-0057-0072, LT123-0080, 0007-0010");
+        let expected = vec![Group::new(
+            "A".to_string(),
+            vec![
+                Range::with_prefix("LT123-".into(), 57, 72, 4),
+                Range::with_prefix("LT123-".into(), 80, 80, 4),
+                Range::with_prefix("".into(), 7, 10, 4),
+            ],
+        )];
+
+        assert_eq!(result, Ok(expected));
+    }
+
+    #[test]
+    fn overlapping_serial_numbers() {
+        let result = parse("A=LT1-LT5, LT3, XT3");
+        assert_eq!(
+            result,
+            Err(vec![InventoryParseError::OverlappingRange {
+                first: OwnedSpan::new(2, 9, "LT1-LT5".into()),
+                overlap: OwnedSpan::new(11, 14, "LT3".into()),
+            }])
+        );
+    }
+
+    #[test]
+    fn largest_unit_numbers() {
+        let result = parse("A=0-18446744073709551614").unwrap();
+        assert_eq!(result, vec![Group::new("A".into(), vec![Range::new(0, range::MAX_UNIT)])]);
+        assert_eq!(result[0].count(), u64::MAX);
+    }
+
+    #[test]
+    fn number_too_large() {
+        let result = parse("A=18446744073709551615, LT99999999999999999999");
+        assert_eq!(
+            result,
+            Err(vec![
+                InventoryParseError::NumberTooLarge {
+                    number: OwnedSpan::new(2, 22, "18446744073709551615".into()),
+                },
+                InventoryParseError::NumberTooLarge {
+                    number: OwnedSpan::new(24, 46, "LT99999999999999999999".into()),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let result = parse("# Lot 7\nEVAL=1-50 !7 # owner: Alice\nCTRL=51-60,\n# spares\n61 #\n");
+        assert_eq!(
+            result,
+            Ok(vec![
+                Group::new("EVAL".into(), vec![Range::new(1, 6), Range::new(8, 50)])
+                    .with_comment("owner: Alice".into()),
+                Group::new("CTRL".into(), vec![Range::new(51, 60), Range::num(61)]).with_comment("".into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn quoted_names() {
+        let result = parse(r#""HTOL 125C"=1-50, "5\" WAFER"=51, "A"=60"#);
+        assert_eq!(
+            result,
+            Ok(vec![
+                Group::new("HTOL 125C".into(), vec![Range::new(1, 50)]),
+                Group::new("5\" WAFER".into(), vec![Range::num(51)]),
+                Group::new("A".into(), vec![Range::num(60)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn quoted_names_round_trip() {
+        let groups = vec![
+            Group::new("ESD CDM".into(), vec![Range::new(1, 50), Range::num(61)]),
+            Group::new("A=B, #1".into(), vec![Range::new(70, 80)]),
+            Group::new("A&B".into(), vec![Range::num(90)]),
+        ];
+        let text = format!("{}", crate::group::Groups(&groups));
+        assert_eq!(parse(&text), Ok(groups));
+    }
+
+    #[test]
+    fn expressions() {
+        let input = "ALL=1-20\nFAILED=3, 7-9\nGOOD = ALL - FAILED\nEVAL = 1-200 - (57-60, 99)\nX = GOOD & (5-12 | 19)";
+        let options = ParseOptions {
+            allow_shared_units: true,
+        };
+        let result = parse_with(input, &options, &mut vec![]);
+        assert_eq!(
+            result,
+            Ok(vec![
+                Group::new("ALL".into(), vec![Range::new(1, 20)]),
+                Group::new("FAILED".into(), vec![Range::num(3), Range::new(7, 9)]),
+                Group::new(
+                    "GOOD".into(),
+                    vec![Range::new(1, 2), Range::new(4, 6), Range::new(10, 20)]
+                ),
+                Group::new(
+                    "EVAL".into(),
+                    vec![Range::new(1, 56), Range::new(61, 98), Range::new(100, 200)]
+                ),
+                Group::new(
+                    "X".into(),
+                    vec![Range::new(5, 6), Range::new(10, 12), Range::num(19)]
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn expressions_left_to_right() {
+        let result = parse("A = 1-10 - 5-10 | 8 # lot 7\nB = \"A\" & (2, 8) C=A");
+        assert_eq!(
+            result,
+            Ok(vec![
+                Group::new("A".into(), vec![Range::new(1, 4), Range::num(8)]).with_comment("lot 7".into()),
+                Group::new("B".into(), vec![Range::num(2), Range::num(8)]),
+                Group::new("C".into(), vec![Range::new(1, 4), Range::num(8)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn spaced_hyphen_is_difference() {
+        assert_eq!(parse("A = 100 - 5"), Ok(vec![Group::new("A".into(), vec![Range::num(100)])]));
+        assert_eq!(
+            parse("A = 1-200 - 57 - 60"),
+            Ok(vec![Group::new(
+                "A".into(),
+                vec![Range::new(1, 56), Range::new(58, 59), Range::new(61, 200)]
+            )])
+        );
+    }
+
+    #[test]
+    fn comment_after_parentheses() {
+        let result = parse("A = 1-10 - (2 | 3) # lot 7
+B=11");
+        assert_eq!(
+            result,
+            Ok(vec![
+                Group::new("A".into(), vec![Range::num(1), Range::new(4, 10)]).with_comment("lot 7".into()),
+                Group::new("B".into(), vec![Range::num(11)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn forward_reference() {
+        let result = parse("GOOD = ALL - 2 ALL=1-3");
+        assert_eq!(
+            result,
+            Ok(vec![
+                Group::new("GOOD".into(), vec![Range::num(1), Range::num(3)]),
+                Group::new("ALL".into(), vec![Range::new(1, 3)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn undefined_group() {
+        let result = parse("A=1-10 B = A & PASSED");
+        assert_eq!(
+            result,
+            Err(vec![InventoryParseError::UndefinedGroup {
+                reference: OwnedSpan::new(15, 21, "PASSED".into()),
+            }])
+        );
+    }
+
+    #[test]
+    fn shared_units() {
+        let input = "EVAL=1-50, CTRL=40-60 # lot 7\nHTOL=45, 70 GOOD = ALL - 145 ALL=101-200";
+        let overlaps = vec![
+            InventoryParseError::SharedUnits {
+                first: OwnedSpan::new(0, 9, "EVAL=1-50".into()),
+                overlap: OwnedSpan::new(11, 21, "CTRL=40-60".into()),
+                units: Range::new(40, 50).into(),
+            },
+            InventoryParseError::SharedUnits {
+                first: OwnedSpan::new(0, 9, "EVAL=1-50".into()),
+                overlap: OwnedSpan::new(30, 41, "HTOL=45, 70".into()),
+                units: Range::num(45).into(),
+            },
+            InventoryParseError::SharedUnits {
+                first: OwnedSpan::new(11, 21, "CTRL=40-60".into()),
+                overlap: OwnedSpan::new(30, 41, "HTOL=45, 70".into()),
+                units: Range::num(45).into(),
+            },
+        ];
+        assert_eq!(parse(input).unwrap_err(), overlaps);
+
+        let options = ParseOptions {
+            allow_shared_units: true,
+        };
+        let mut warnings = vec![];
+        let groups = parse_with(input, &options, &mut warnings).unwrap();
+        assert_eq!(groups.len(), 5);
+        assert_eq!(warnings, overlaps);
+        assert_eq!(
+            groups[3],
+            Group::new("GOOD".into(), vec![Range::new(101, 144), Range::new(146, 200)])
+        );
+        assert_eq!(
+            overlaps[1].to_string(),
+            "Units are in more than one group: 45 in OwnedSpan { start: 30, end: 41, text: \"HTOL=45, 70\" } are also in OwnedSpan { start: 0, end: 9, text: \"EVAL=1-50\" }"
+        );
+    }
+
+    #[test]
+    fn expression_operands_share_units() {
+        // The example from the README
+        let input = "PASSED=1-150\nRETEST=151-200\nGOOD = PASSED | RETEST - (57-60, 99)\nEVAL = GOOD & 1-120";
+        let groups = parse(input).unwrap();
+        assert_eq!(
+            groups[3],
+            Group::new(
+                "EVAL".into(),
+                vec![Range::new(1, 56), Range::new(61, 98), Range::new(100, 120)]
+            )
+        );
+
+        let groups = parse("ALL=1-100, FAILED=5, 9, GOOD = ALL - FAILED").unwrap();
+        assert_eq!(groups[1], Group::new("FAILED".into(), vec![Range::num(5), Range::num(9)]));
+        assert_eq!(groups[2].count(), 98);
+    }
+
+    #[test]
+    fn referenced_groups_share_units() {
+        // Referring to a group doesn't stop it from being checked against unrelated groups
+        for input in &["EVAL=1-50, CTRL=40-60", "EVAL=1-50, CTRL=40-60, X = EVAL"] {
+            assert_eq!(
+                parse(input).unwrap_err(),
+                vec![InventoryParseError::SharedUnits {
+                    first: OwnedSpan::new(0, 9, "EVAL=1-50".into()),
+                    overlap: OwnedSpan::new(11, 21, "CTRL=40-60".into()),
+                    units: Range::new(40, 50).into(),
+                }]
+            );
+        }
+
+        // FAILED and ALL are both used by GOOD, but PASSED is unrelated to FAILED
+        let input = "ALL=1-100, FAILED=5, 9, PASSED=1-8, GOOD = ALL - FAILED";
+        assert_eq!(
+            parse(input).unwrap_err(),
+            vec![
+                InventoryParseError::SharedUnits {
+                    first: OwnedSpan::new(0, 9, "ALL=1-100".into()),
+                    overlap: OwnedSpan::new(24, 34, "PASSED=1-8".into()),
+                    units: Range::new(1, 8).into(),
+                },
+                InventoryParseError::SharedUnits {
+                    first: OwnedSpan::new(11, 22, "FAILED=5, 9".into()),
+                    overlap: OwnedSpan::new(24, 34, "PASSED=1-8".into()),
+                    units: Range::num(5).into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn stepped_ranges() {
+        parses_to! {
+            parser: InventoryParser,
+            input: "A=1-100/2 LT1-LT9 step 4",
+            rule: Rule::inventory,
+            tokens: [
+                inventory(0, 24, [
+                    group(0, 24, [
+                        name(0, 1),
+                        stepped(2, 9, [
+                            range(2, 7, [number(2, 3), number(4, 7)]),
+                            step(8, 9)
+                        ]),
+                        stepped(10, 24, [
+                            serial(10, 17),
+                            step(23, 24)
+                        ])
+                    ]),
+                    EOI(24, 24)
+                ])
+            ]
+        };
+    }
+
+    #[test]
+    fn stepped_ranges_to_data() {
+        let result = parse("ODD=1-100/2, EVEN=2-100 step 2 !50, 52\nLT=LT001-LT009/4");
+        assert_eq!(
+            result,
+            Ok(vec![
+                Group::new("ODD".into(), vec![Range::new(1, 99).with_step(2)]),
+                Group::new(
+                    "EVEN".into(),
+                    vec![
+                        Range::new(2, 48).with_step(2),
+                        Range::new(54, 100).with_step(2)
+                    ]
+                ),
+                Group::new("LT".into(), vec![Range::with_prefix("LT".into(), 1, 9, 3).with_step(4)]),
+            ])
+        );
+        assert_eq!(
+            parse("A=1-10/0, 7-20/3, 13"),
+            Err(vec![
+                InventoryParseError::ZeroStep {
+                    step: OwnedSpan::new(7, 8, "0".into()),
+                },
+                InventoryParseError::OverlappingRange {
+                    first: OwnedSpan::new(10, 16, "7-20/3".into()),
+                    overlap: OwnedSpan::new(18, 20, "13".into()),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn cyclic_groups() {
+        let result = parse("A = B | 1 B = 2 - A C = C");
+        assert_eq!(
+            result,
+            Err(vec![
+                InventoryParseError::CyclicGroup {
+                    reference: OwnedSpan::new(18, 19, "A".into()),
+                },
+                InventoryParseError::CyclicGroup {
+                    reference: OwnedSpan::new(24, 25, "C".into()),
+                },
+            ])
+        );
+    }
 }
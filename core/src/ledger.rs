@@ -0,0 +1,490 @@
+//! A record of the units that have been given out by earlier splits, so that later
+//! splits only use the units that are still available.
+//!
+//! A ledger is kept as text, with each committed split written as a `commit` line
+//! followed by the units each request was given, in the inventory notation. Units
+//! that are given back are written the same way after a `return` line:
+//!
+//! ```text
+//! commit 1718000000
+//! A: EVAL=1-10, CTRL=21-25
+//! "B C": EVAL=11-12
+//!
+//! return 1718090000
+//! A: EVAL=3-4
+//! ```
+//!
+//! The timestamp is the number of seconds since the Unix epoch. Blank lines and lines
+//! starting with `#` are ignored.
+
+use crate::group::{self, Group, Groups, Name};
+use crate::inventory::{self, InventoryParseError, OwnedSpan};
+use crate::range::Range;
+use crate::range_set::RangeSet;
+use crate::split::{Split, SplitError};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ledger {
+    entries: Vec<Entry>,
+}
+
+/// Units that were given out by a split, or given back by requests
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub kind: EntryKind,
+    /// When the units were given out or back, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// The units given to or by each request, like `Split::filled_requests`
+    pub requests: Vec<(String, Vec<Group>)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntryKind {
+    /// The units were given out by a split
+    Commit,
+    /// The units were given back
+    Return,
+}
+
+impl EntryKind {
+    fn keyword(self) -> &'static str {
+        match self {
+            EntryKind::Commit => "commit",
+            EntryKind::Return => "return",
+        }
+    }
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Records the units given out by `split`. The time is left to the caller, so that
+    /// the core doesn't depend on a clock.
+    pub fn commit(&mut self, split: &Split, timestamp: u64) {
+        self.entries.push(Entry {
+            kind: EntryKind::Commit,
+            timestamp,
+            requests: split.filled_requests.clone(),
+        });
+    }
+
+    /// Gives units that a request is holding back, like `Split::return_units`, and
+    /// records it. A request holds the units given to it by every commit under its
+    /// name, less any it has already given back. Nothing is recorded if the request
+    /// doesn't hold any units.
+    pub fn return_units(
+        &mut self,
+        request_name: &str,
+        units: Option<&[Range]>,
+        timestamp: u64,
+    ) -> Result<Vec<Group>, Vec<SplitError>> {
+        let held = self.held(request_name);
+        if held.is_empty() {
+            return Err(vec![SplitError::UnknownRequest {
+                request_name: request_name.into(),
+            }]);
+        }
+        if held.iter().all(|group| group.units().is_empty()) {
+            return Err(vec![SplitError::NothingHeld {
+                request_name: request_name.into(),
+            }]);
+        }
+        let mut split = Split {
+            filled_requests: vec![(request_name.into(), held)],
+            leftover_ranges: vec![],
+            shortfalls: vec![],
+        };
+        let returned = split.return_units(request_name, units)?;
+        self.entries.push(Entry {
+            kind: EntryKind::Return,
+            timestamp,
+            requests: vec![(request_name.into(), returned.clone())],
+        });
+        Ok(returned)
+    }
+
+    /// Every unit that has been given out and not given back, by group, in the order
+    /// the groups were first given out from
+    pub fn committed(&self) -> Vec<Group> {
+        // Worked out request by request, since a request that reuses the units of
+        // another request holds the same units, and giving them back from one of them
+        // doesn't free them
+        let mut request_names: Vec<&str> = vec![];
+        for (request_name, _) in self.entries.iter().flat_map(|entry| &entry.requests) {
+            if !request_names.contains(&request_name.as_str()) {
+                request_names.push(request_name);
+            }
+        }
+        let held: Vec<Group> = request_names.into_iter().flat_map(|name| self.held(name)).collect();
+        self.replay(|_| true)
+            .into_iter()
+            .map(|group| {
+                let units = held
+                    .iter()
+                    .filter(|h| h.name() == group.name())
+                    .fold(RangeSet::new(), |units, h| &units | h.units());
+                group.with_units(units)
+            })
+            .collect()
+    }
+
+    /// The units still held by a request
+    pub fn held(&self, request_name: &str) -> Vec<Group> {
+        self.replay(|name| name == request_name)
+    }
+
+    /// Works out which units are given out to the requests that `include` picks, by
+    /// going through the entries in order
+    fn replay<F: Fn(&str) -> bool>(&self, include: F) -> Vec<Group> {
+        let mut names: Vec<&str> = vec![];
+        let mut units: HashMap<&str, RangeSet> = HashMap::new();
+        for entry in &self.entries {
+            for (_request_name, groups) in entry.requests.iter().filter(|(name, _)| include(name)) {
+                for group in groups {
+                    let given_out = units.entry(group.name()).or_insert_with(|| {
+                        names.push(group.name());
+                        RangeSet::new()
+                    });
+                    *given_out = match entry.kind {
+                        EntryKind::Commit => &*given_out | group.units(),
+                        EntryKind::Return => &*given_out - group.units(),
+                    };
+                }
+            }
+        }
+        names
+            .into_iter()
+            .map(|name| Group::new(name.into(), units.remove(name).unwrap_or_default().into_ranges()))
+            .collect()
+    }
+
+    /// The units in `inventory` that haven't been given out yet. Units are matched to
+    /// the inventory by group name. Every group is kept, in the same order, even if all
+    /// of its units are gone, so that amounts matched to groups by position still line
+    /// up.
+    pub fn available(&self, inventory: &[Group]) -> Vec<Group> {
+        let committed = self.committed();
+        inventory
+            .iter()
+            .map(|group| match committed.iter().find(|c| c.name() == group.name()) {
+                Some(c) => group.without(c.units()),
+                None => group.clone(),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Ledger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, entry) in self.entries.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{} {}", entry.kind.keyword(), entry.timestamp)?;
+            for (request_name, groups) in &entry.requests {
+                // Groups that were skipped or given nothing can't be written down
+                let groups: Vec<Group> = groups.iter().filter(|g| !g.ranges().is_empty()).cloned().collect();
+                if groups.is_empty() {
+                    writeln!(f, "{}:", Name(request_name))?;
+                } else {
+                    writeln!(f, "{}: {}", Name(request_name), Groups(&groups))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn parse(input: &str) -> Result<Ledger, Vec<LedgerParseError>> {
+    let mut entries: Vec<Entry> = vec![];
+    let mut errors = vec![];
+    let mut line_start = 0;
+    for line in input.split('\n') {
+        let start = line_start;
+        line_start += line.len() + 1;
+        let text = line.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let span = || {
+            let offset = start + (line.len() - line.trim_start().len());
+            OwnedSpan::new(offset, offset + text.len(), text.into())
+        };
+
+        let header = [EntryKind::Commit, EntryKind::Return]
+            .iter()
+            .find_map(|&kind| Some((kind, text.strip_prefix(kind.keyword())?.strip_prefix(' ')?)));
+        if let Some((kind, timestamp)) = header {
+            match timestamp.trim().parse() {
+                Ok(timestamp) => entries.push(Entry {
+                    kind,
+                    timestamp,
+                    requests: vec![],
+                }),
+                Err(_) => errors.push(LedgerParseError::InvalidTimestamp { line: span() }),
+            }
+            continue;
+        }
+
+        let entry = match entries.last_mut() {
+            Some(entry) => entry,
+            None => {
+                errors.push(LedgerParseError::MissingCommit { line: span() });
+                continue;
+            }
+        };
+        let (name, groups) = match split_request_name(text) {
+            Some(parts) => parts,
+            None => {
+                errors.push(LedgerParseError::Syntax { line: span() });
+                continue;
+            }
+        };
+        let groups = if groups.trim().is_empty() {
+            vec![]
+        } else {
+            let options = inventory::ParseOptions {
+                allow_shared_units: true,
+            };
+            match inventory::parse_with(groups, &options, &mut vec![]) {
+                Ok(groups) => groups,
+                Err(inventory_errors) => {
+                    errors.push(LedgerParseError::Groups {
+                        line: span(),
+                        errors: inventory_errors,
+                    });
+                    continue;
+                }
+            }
+        };
+        entry.requests.push((group::unquote(name), groups));
+    }
+
+    if errors.is_empty() {
+        Ok(Ledger { entries })
+    } else {
+        Err(errors)
+    }
+}
+
+/// Splits a line like `"B C": EVAL=11-12` into the request name and the groups
+fn split_request_name(line: &str) -> Option<(&str, &str)> {
+    let name_end = if let Some(quoted) = line.strip_prefix('"') {
+        let mut escaped = false;
+        let closing = quoted.char_indices().find(|&(_, c)| {
+            let closing = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            closing
+        })?;
+        closing.0 + 2
+    } else {
+        line.find(':')?
+    };
+    let (name, rest) = line.split_at(name_end);
+    let groups = rest.trim_start().strip_prefix(':')?;
+    Some((name.trim(), groups))
+}
+
+#[derive(Fail, Debug, PartialEq)]
+pub enum LedgerParseError {
+    #[fail(display = "Expected a request name followed by a colon: {:?}", line)]
+    Syntax { line: OwnedSpan },
+
+    #[fail(display = "Expected a number of seconds since the Unix epoch: {:?}", line)]
+    InvalidTimestamp { line: OwnedSpan },
+
+    #[fail(display = "Units are listed before the first commit or return: {:?}", line)]
+    MissingCommit { line: OwnedSpan },
+
+    /// The positions in `errors` are counted from the start of the units, after the
+    /// request name
+    #[fail(display = "Invalid units in {:?}: {:?}", line, errors)]
+    Groups {
+        line: OwnedSpan,
+        errors: Vec<InventoryParseError>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::group::Group;
+    use crate::inventory::OwnedSpan;
+    use crate::ledger::{parse, Ledger, LedgerParseError};
+    use crate::range::Range;
+    use crate::request::{Amount, Request};
+    use crate::split::{split, SplitError};
+
+    #[test]
+    fn commit_and_replay() {
+        let inventory = vec![
+            Group::new("EVAL".into(), vec![Range::new(1, 20)]),
+            Group::new("CTRL".into(), vec![Range::new(21, 30)]),
+        ];
+        let mut ledger = Ledger::new();
+
+        let monday = vec![
+            Request::new("A".into(), vec![Amount::new(10), Amount::new(5)]),
+            Request::new("B C".into(), vec![Amount::new(2)]),
+        ];
+        ledger.commit(&split(&inventory, &monday).unwrap(), 1_718_000_000);
+        let available = ledger.available(&inventory);
+        assert_eq!(
+            available,
+            vec![
+                Group::new("EVAL".into(), vec![Range::new(13, 20)]),
+                Group::new("CTRL".into(), vec![Range::new(26, 30)]),
+            ]
+        );
+
+        let tuesday = vec![Request::new("D".into(), vec![Amount::new(8), Amount::new(5)])];
+        ledger.commit(&split(&available, &tuesday).unwrap(), 1_718_086_400);
+        assert_eq!(
+            ledger.available(&inventory),
+            vec![
+                Group::new("EVAL".into(), vec![]),
+                Group::new("CTRL".into(), vec![]),
+            ]
+        );
+        assert_eq!(
+            ledger.committed(),
+            vec![
+                Group::new("EVAL".into(), vec![Range::new(1, 20)]),
+                Group::new("CTRL".into(), vec![Range::new(21, 30)]),
+            ]
+        );
+
+        let text = ledger.to_string();
+        assert_eq!(
+            text,
+            "commit 1718000000\nA: EVAL=1-10, CTRL=21-25\n\"B C\": EVAL=11-12\n\ncommit 1718086400\nD: EVAL=13-20, CTRL=26-30\n"
+        );
+        assert_eq!(parse(&text), Ok(ledger));
+    }
+
+    #[test]
+    fn parse_ledger() {
+        let input = "# Lot 7\ncommit 1718000000\n  A: EVAL=1-9/2 # for Alice\n\"x: \\\"y\\\"\" : CTRL=LT5\nB:\n";
+        let ledger = parse(input).unwrap();
+        assert_eq!(ledger.entries().len(), 1);
+        assert_eq!(
+            ledger.entries()[0].requests,
+            vec![
+                (
+                    "A".into(),
+                    vec![Group::new("EVAL".into(), vec![Range::new(1, 9).with_step(2)]).with_comment("for Alice".into())]
+                ),
+                (
+                    "x: \"y\"".into(),
+                    vec![Group::new("CTRL".into(), vec![Range::with_prefix("LT".into(), 5, 5, 0)])]
+                ),
+                ("B".into(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn return_units() {
+        let inventory = vec![Group::new("EVAL".into(), vec![Range::new(1, 20)])];
+        let mut ledger = Ledger::new();
+        let requests = vec![
+            Request::new("A".into(), vec![Amount::new(10)]),
+            Request::new("B".into(), vec![Amount::new(5)]),
+        ];
+        ledger.commit(&split(&inventory, &requests).unwrap(), 100);
+
+        let returned = ledger.return_units("A", Some(&[Range::new(3, 4)]), 200).unwrap();
+        assert_eq!(returned, vec![Group::new("EVAL".into(), vec![Range::new(3, 4)])]);
+        assert_eq!(
+            ledger.return_units("A", Some(&[Range::num(4)]), 300),
+            Err(vec![SplitError::UnitsNotAllocated {
+                request_name: "A".into(),
+                units: Range::num(4),
+            }])
+        );
+        assert_eq!(
+            ledger.available(&inventory),
+            vec![Group::new("EVAL".into(), vec![Range::new(3, 4), Range::new(16, 20)])]
+        );
+        assert_eq!(
+            ledger.held("A"),
+            vec![Group::new("EVAL".into(), vec![Range::new(1, 2), Range::new(5, 10)])]
+        );
+
+        ledger.return_units("B", None, 400).unwrap();
+        assert_eq!(ledger.held("B"), vec![Group::new("EVAL".into(), vec![])]);
+        assert_eq!(
+            ledger.return_units("B", None, 450),
+            Err(vec![SplitError::NothingHeld {
+                request_name: "B".into(),
+            }])
+        );
+        assert_eq!(
+            ledger.return_units("C", None, 500),
+            Err(vec![SplitError::UnknownRequest {
+                request_name: "C".into(),
+            }])
+        );
+
+        let text = ledger.to_string();
+        assert_eq!(
+            text,
+            "commit 100\nA: EVAL=1-10\nB: EVAL=11-15\n\nreturn 200\nA: EVAL=3-4\n\nreturn 400\nB: EVAL=11-15\n"
+        );
+        assert_eq!(parse(&text), Ok(ledger));
+    }
+
+    #[test]
+    fn invalid_ledger() {
+        let result = parse("A: EVAL=1\ncommit soon\ncommit 5\nB EVAL=2\nC: EVAL=3-2");
+        assert_eq!(
+            result,
+            Err(vec![
+                LedgerParseError::MissingCommit {
+                    line: OwnedSpan::new(0, 9, "A: EVAL=1".into()),
+                },
+                LedgerParseError::InvalidTimestamp {
+                    line: OwnedSpan::new(10, 21, "commit soon".into()),
+                },
+                LedgerParseError::Syntax {
+                    line: OwnedSpan::new(31, 39, "B EVAL=2".into()),
+                },
+                LedgerParseError::Groups {
+                    line: OwnedSpan::new(40, 51, "C: EVAL=3-2".into()),
+                    errors: vec![crate::inventory::InventoryParseError::DecreasingRange {
+                        range: OwnedSpan::new(6, 9, "3-2".into()),
+                    }],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn return_reused_units() {
+        let inventory = vec![Group::new("EVAL".into(), vec![Range::new(1, 20)])];
+        let mut ledger = Ledger::new();
+        let requests = vec![
+            Request::new("TC".into(), vec![Amount::new(10)]),
+            Request::same_as("HAST".into(), "TC".into()),
+        ];
+        ledger.commit(&split(&inventory, &requests).unwrap(), 100);
+
+        ledger.return_units("HAST", None, 200).unwrap();
+        assert_eq!(
+            ledger.available(&inventory),
+            vec![Group::new("EVAL".into(), vec![Range::new(11, 20)])]
+        );
+        ledger.return_units("TC", Some(&[Range::new(1, 4)]), 300).unwrap();
+        assert_eq!(
+            ledger.available(&inventory),
+            vec![Group::new("EVAL".into(), vec![Range::new(1, 4), Range::new(11, 20)])]
+        );
+    }
+}
@@ -6,10 +6,14 @@ extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
+pub mod format;
 pub mod group;
 pub mod interval_tree;
 pub mod inventory;
+pub mod ledger;
+pub mod pipeline;
 pub mod range;
+pub mod range_set;
 pub mod request;
 pub mod requests;
 pub mod split;
@@ -0,0 +1,344 @@
+//! Splits that happen in stages, where each stage splits up the units left over by the
+//! stage before it.
+//!
+//! A pipeline is written in the requests notation, with a `[name]` line before the
+//! requests of each stage:
+//!
+//! ```text
+//! [precondition]
+//! PC: 20, 10
+//!
+//! [reliability]
+//! HTOL: 10
+//! THB: 10, 5
+//!
+//! [spares]
+//! SPARE: *, *
+//! ```
+//!
+//! Requests before the first stage header make up a stage without a name, so requests
+//! without any headers are a pipeline with one stage.
+
+use crate::group::{self, Group};
+use crate::inventory::OwnedSpan;
+use crate::request::Request;
+use crate::requests::{self, ParseOptions, RequestsParseError};
+use crate::split::{self, GivenTwice, Split, SplitError, SplitOptions};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stage {
+    name: String,
+    requests: Vec<Request>,
+}
+
+impl Stage {
+    pub fn new(name: String, requests: Vec<Request>) -> Self {
+        Self { name, requests }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn requests(&self) -> &[Request] {
+        &self.requests
+    }
+}
+
+pub fn parse(input: &str) -> Result<Vec<Stage>, Vec<PipelineParseError>> {
+    parse_with(input, &ParseOptions::default())
+}
+
+/// Parses a pipeline, reading the requests of each stage with `options`. Requests in
+/// different stages can have the same name.
+pub fn parse_with(input: &str, options: &ParseOptions) -> Result<Vec<Stage>, Vec<PipelineParseError>> {
+    let lines: Vec<&str> = input.split('\n').collect();
+    let mut names = vec![String::new()];
+    let mut headers: Vec<Option<OwnedSpan>> = vec![None];
+    // The stage each line belongs to, or `None` for stage headers
+    let mut line_stages = Vec::with_capacity(lines.len());
+    let mut errors = vec![];
+    let mut line_start = 0;
+    for line in &lines {
+        match stage_header(line) {
+            Some(name) => {
+                let start = line_start + (line.len() - line.trim_start().len());
+                let header = OwnedSpan::new(start, start + line.trim().len(), line.trim().into());
+                let first = names.iter().position(|n| *n == name).and_then(|idx| headers[idx].clone());
+                if let Some(first) = first {
+                    errors.push(PipelineParseError::DuplicateStage {
+                        first,
+                        duplicate: header.clone(),
+                    });
+                }
+                names.push(name);
+                headers.push(Some(header));
+                line_stages.push(None);
+            }
+            None => line_stages.push(Some(names.len() - 1)),
+        }
+        line_start += line.len() + 1;
+    }
+
+    let mut stages = vec![];
+    for (stage_idx, name) in names.into_iter().enumerate() {
+        let has_requests = lines.iter().zip(&line_stages).any(|(line, &stage)| {
+            let line = line.trim();
+            stage == Some(stage_idx) && !line.is_empty() && !line.starts_with('#')
+        });
+        if !has_requests {
+            // Leave out the stage without a name if nothing comes before the first header
+            if stage_idx > 0 || headers.len() == 1 {
+                stages.push(Stage::new(name, vec![]));
+            }
+            continue;
+        }
+
+        // Every line that isn't part of this stage is turned into a comment of the same
+        // length, so that positions in errors are the same as in `input`
+        let text = lines
+            .iter()
+            .zip(&line_stages)
+            .map(|(line, &stage)| {
+                if stage == Some(stage_idx) || line.is_empty() {
+                    (*line).to_string()
+                } else {
+                    format!("#{:1$}", "", line.len() - 1)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        match requests::parse_with(&text, options) {
+            Ok(requests) => stages.push(Stage::new(name, requests)),
+            Err(request_errors) => errors.extend(request_errors.into_iter().map(PipelineParseError::Requests)),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(stages)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Whether `line` is a stage header, like `[htol]`
+pub fn is_stage_header(line: &str) -> bool {
+    stage_header(line).is_some()
+}
+
+/// Reads the name of a stage from a header like `[htol]`, which can have a comment
+/// after it
+pub(crate) fn stage_header(line: &str) -> Option<String> {
+    let rest = line.trim().strip_prefix('[')?;
+    let end = rest.find(']')?;
+    let after = rest[end + 1..].trim_start();
+    let name = rest[..end].trim();
+    if name.contains(':') || !(after.is_empty() || after.starts_with('#')) {
+        return None;
+    }
+    Some(group::unquote(name))
+}
+
+#[derive(Fail, Debug, PartialEq)]
+pub enum PipelineParseError {
+    #[fail(display = "{}", _0)]
+    Requests(#[cause] RequestsParseError),
+
+    #[fail(
+        display = "Duplicate stage name: stage at {:?} has the same name as stage at {:?}",
+        duplicate, first
+    )]
+    DuplicateStage {
+        first: OwnedSpan,
+        duplicate: OwnedSpan,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Pipeline {
+    /// The name and split of each stage, in order
+    pub stages: Vec<(String, Split)>,
+    /// The units left over after the last stage
+    pub leftover_ranges: Vec<Group>,
+}
+
+impl Pipeline {
+    /// The units that were given out from more than one group, in any of the stages.
+    /// See `Split::units_given_twice`.
+    pub fn units_given_twice(&self) -> Vec<GivenTwice> {
+        split::given_twice(
+            self.stages
+                .iter()
+                .flat_map(|(_, split)| split.filled_requests.iter().flat_map(|(_, groups)| groups)),
+        )
+    }
+}
+
+/// The errors from the first stage that couldn't be split. Later stages aren't split,
+/// since there is nothing for them to split.
+#[derive(Debug, PartialEq)]
+pub struct StageErrors {
+    pub stage: String,
+    pub errors: Vec<SplitError>,
+}
+
+pub fn split(inventory: &[Group], stages: &[Stage]) -> Result<Pipeline, StageErrors> {
+    split_with(inventory, stages, &SplitOptions::default())
+}
+
+/// Splits `inventory` between the requests of the first stage, then splits what is left
+/// over between the requests of the next stage, and so on. Every stage is split with
+/// the same options.
+pub fn split_with(inventory: &[Group], stages: &[Stage], options: &SplitOptions) -> Result<Pipeline, StageErrors> {
+    let mut inventory = inventory.to_vec();
+    let mut splits = vec![];
+    for stage in stages {
+        let split = split::split_with(&inventory, stage.requests(), options).map_err(|errors| StageErrors {
+            stage: stage.name().into(),
+            errors,
+        })?;
+        inventory = split.leftover_ranges.clone();
+        splits.push((stage.name().into(), split));
+    }
+    Ok(Pipeline {
+        stages: splits,
+        leftover_ranges: inventory,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::group::Group;
+    use crate::inventory::OwnedSpan;
+    use crate::pipeline::{parse, split, PipelineParseError, Stage, StageErrors};
+    use crate::range::Range;
+    use crate::request::{Amount, Request};
+    use crate::requests::RequestsParseError;
+    use crate::split::{GivenTwice, SplitError};
+
+    #[test]
+    fn parse_stages() {
+        let input = "[precondition]  # 24h bake\nPC: 20, 10\n\n[reliability]\nHTOL: 10\nTHB: 10, 5 # 85C\n[\"spare units\"]\n# nothing yet\n";
+        assert_eq!(
+            parse(input),
+            Ok(vec![
+                Stage::new(
+                    "precondition".into(),
+                    vec![Request::new("PC".into(), vec![Amount::new(20), Amount::new(10)])]
+                ),
+                Stage::new(
+                    "reliability".into(),
+                    vec![
+                        Request::new("HTOL".into(), vec![Amount::new(10)]),
+                        Request::new("THB".into(), vec![Amount::new(10), Amount::new(5)]).with_comment("85C".into()),
+                    ]
+                ),
+                Stage::new("spare units".into(), vec![]),
+            ])
+        );
+    }
+
+    #[test]
+    fn requests_without_stages() {
+        assert_eq!(
+            parse("A: 5\nB: 6"),
+            Ok(vec![Stage::new(
+                "".into(),
+                vec![
+                    Request::new("A".into(), vec![Amount::new(5)]),
+                    Request::new("B".into(), vec![Amount::new(6)]),
+                ]
+            )])
+        );
+        assert_eq!(
+            parse("A: 5\n[later]\nA: 6").unwrap()[0],
+            Stage::new("".into(), vec![Request::new("A".into(), vec![Amount::new(5)])])
+        );
+    }
+
+    #[test]
+    fn stage_errors() {
+        let result = parse("[a]\nA: 5\n[b]\nB: 6x0\n [a]\n");
+        assert_eq!(
+            result,
+            Err(vec![
+                PipelineParseError::DuplicateStage {
+                    first: OwnedSpan::new(0, 3, "[a]".into()),
+                    duplicate: OwnedSpan::new(21, 24, "[a]".into()),
+                },
+                PipelineParseError::Requests(RequestsParseError::ZeroRepetition {
+                    repetition: OwnedSpan::new(16, 19, "6x0".into()),
+                }),
+            ])
+        );
+    }
+
+    #[test]
+    fn split_stages() {
+        let inventory = vec![
+            Group::new("A".into(), vec![Range::new(1, 20)]),
+            Group::new("B".into(), vec![Range::new(21, 30)]),
+        ];
+        let stages = parse("[pc]\nPC: 15, 5\n[htol]\nHTOL: 3, 2\nTHB: 1\n[spares]\nSPARE: *, *").unwrap();
+        let pipeline = split(&inventory, &stages).unwrap();
+
+        let names: Vec<&str> = pipeline.stages.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["pc", "htol", "spares"]);
+        assert_eq!(
+            pipeline.stages[1].1.filled_requests,
+            vec![
+                (
+                    "HTOL".into(),
+                    vec![
+                        Group::new("A".into(), vec![Range::new(16, 18)]),
+                        Group::new("B".into(), vec![Range::new(26, 27)]),
+                    ]
+                ),
+                ("THB".into(), vec![Group::new("A".into(), vec![Range::num(19)])]),
+            ]
+        );
+        assert_eq!(
+            pipeline.stages[2].1.filled_requests[0].1,
+            vec![
+                Group::new("A".into(), vec![Range::num(20)]),
+                Group::new("B".into(), vec![Range::new(28, 30)]),
+            ]
+        );
+        assert_eq!(
+            pipeline.leftover_ranges,
+            vec![Group::new("A".into(), vec![]), Group::new("B".into(), vec![])]
+        );
+    }
+
+    #[test]
+    fn split_stage_errors() {
+        let inventory = vec![Group::new("A".into(), vec![Range::new(1, 20)])];
+        let stages = parse("[pc]\nPC: 15\n[htol]\nHTOL: 6").unwrap();
+        assert_eq!(
+            split(&inventory, &stages),
+            Err(StageErrors {
+                stage: "htol".into(),
+                errors: vec![SplitError::NotEnough {
+                    request_name: "HTOL".into(),
+                    group_name: "A".into(),
+                    amount_needed: 1,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn units_given_twice() {
+        let inventory = crate::inventory::parse("ALL=1-10, GOOD = ALL - 1-5").unwrap();
+        let stages = parse("[pc]\nPC: GOOD=2\n[htol]\nHTOL: ALL=7").unwrap();
+        let pipeline = split(&inventory, &stages).unwrap();
+        assert_eq!(
+            pipeline.units_given_twice(),
+            vec![GivenTwice {
+                first_group: "GOOD".into(),
+                second_group: "ALL".into(),
+                units: Range::new(6, 7).into(),
+            }]
+        );
+    }
+}
@@ -1,76 +1,444 @@
+use crate::range_set::RangeSet;
 use std::fmt;
+use std::str::FromStr;
 
+/// The largest unit number. One less than `u64::MAX`, so that the number of units in
+/// any range, `0-18446744073709551614` included, fits in a `u64`.
+pub const MAX_UNIT: u64 = u64::MAX - 1;
+
+/// A contiguous span of unit numbers, like `1-50`, or every so many units across a
+/// span, like `1-99/2` for the odd units from 1 to 99.
+///
+/// Units can also have a prefix in front of the number, like `LT123-0057`. The prefix
+/// is shared by every unit in the range, and units with different prefixes are never
+/// the same unit. The width is the number of digits the number is padded to with
+/// zeros, so that `007` stays `007`.
+///
+/// The step is the distance from one unit in the range to the next, which is 1 unless
+/// the range has a stride. The last number is always a unit in the range, so `1-100/2`
+/// is kept as `1-99/2`, and a range of one unit always has a step of 1.
 #[derive(Hash, Eq, PartialEq, Clone, Debug)]
 pub struct Range {
-    first: u32,
-    last: u32,
+    prefix: String,
+    first: u64,
+    last: u64,
+    step: u64,
+    width: usize,
 }
 
 impl Range {
-    pub fn new(first: u32, last: u32) -> Self {
-        Self { first, last }
+    /// Unit numbers are expected to be no larger than `MAX_UNIT`
+    pub fn new(first: u64, last: u64) -> Self {
+        Self::with_prefix(String::new(), first, last, 0)
     }
 
     /// A convience function for a range of size one
-    pub fn num(num: u32) -> Self {
+    pub fn num(num: u64) -> Self {
+        Self::new(num, num)
+    }
+
+    pub fn with_prefix(prefix: String, first: u64, last: u64, width: usize) -> Self {
         Self {
-            first: num,
-            last: num,
+            prefix,
+            first,
+            last,
+            step: 1,
+            width,
         }
     }
 
-    pub fn first(&self) -> u32 {
+    /// The same range, but with only every `step`th unit in it, starting from the first.
+    /// The last number is moved down to the last unit that is still in the range.
+    pub fn with_step(self, step: u64) -> Self {
+        assert!(step > 0, "A range must have a step of at least 1");
+        let last = self.first + (self.last - self.first) / step * step;
+        let step = if last == self.first { 1 } else { step };
+        Self { last, step, ..self }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn first(&self) -> u64 {
         self.first
     }
 
-    pub fn last(&self) -> u32 {
+    pub fn last(&self) -> u64 {
         self.last
     }
 
-    pub fn count(&self) -> u32 {
-        self.last - self.first + 1
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn count(&self) -> u64 {
+        (self.last - self.first) / self.step + 1
     }
 
     pub fn write_to_string(&self, string: &mut String) {
         use std::fmt::Write;
-        if self.first == self.last {
-            let _ = write!(string, "{}", self.first);
-        } else {
-            let _ = write!(string, "{}-{}", self.first, self.last);
-        }
+        let _ = write!(string, "{}", self);
+    }
+
+    /// A range with the same prefix, step and width as this one, but different numbers
+    fn span(&self, first: u64, last: u64) -> Self {
+        self.stepped(first, last, self.step)
     }
 
-    pub fn split(&self, amount: u32) -> (Option<Self>, Option<Self>, u32) {
+    /// A range with the same prefix and width as this one, but different numbers and step
+    fn stepped(&self, first: u64, last: u64, step: u64) -> Self {
+        Self::with_prefix(self.prefix.clone(), first, last, self.width).with_step(step)
+    }
+
+    pub fn split(&self, amount: u64) -> (Option<Self>, Option<Self>, u64) {
         if amount == 0 {
             (None, Some(self.clone()), 0)
         } else if amount >= self.count() {
             (Some(self.clone()), None, amount - self.count())
         } else {
-            let other_first = self.first + amount;
-            let this_last = other_first - 1;
+            let this_last = self.first + (amount - 1) * self.step;
+            let other_first = this_last + self.step;
             (
-                Some(Range::new(self.first, this_last)),
-                Some(Range::new(other_first, self.last)),
+                Some(self.span(self.first, this_last)),
+                Some(self.span(other_first, self.last)),
                 0,
             )
         }
     }
 
+    /// Whether `self` and `other` have any units in common
     pub fn overlaps(&self, other: &Self) -> bool {
-        !(self.last < other.first || self.first > other.last)
+        self.intersection(other).is_some()
+    }
+
+    /// Combines `self` and `other` into one range, if together they make up a range:
+    /// they have the same step, and either overlap or one picks up where the other
+    /// leaves off. A single unit can be added onto a range with any step. Ranges padded
+    /// to different widths aren't merged, so that each unit is still written the same way.
+    pub fn merge(&self, other: &Self) -> Option<Self> {
+        if self.prefix != other.prefix || self.width != other.width {
+            return None;
+        }
+        let step = match (self.count() > 1, other.count() > 1) {
+            (true, true) if self.step != other.step => return None,
+            (true, _) => self.step,
+            (false, true) => other.step,
+            (false, false) => 1,
+        };
+        let (low, high) = if self.first <= other.first {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let in_step = (high.first - low.first) % step == 0;
+        let touching = high.first <= low.last || high.first - low.last <= step;
+        if in_step && touching {
+            Some(self.stepped(low.first, low.last.max(high.last), step))
+        } else {
+            None
+        }
     }
+
+    /// The units that are in both `self` and `other`
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let low = u128::from(self.first.max(other.first));
+        let high = u128::from(self.last.min(other.last));
+        if self.prefix != other.prefix || low > high {
+            return None;
+        }
+
+        // The units of both ranges line up every `lcm` units, if they line up at all.
+        // `self.first + self.step * k` is the first place they do, where `k` solves
+        // `self.step * k = other.first - self.first` modulo `other.step`.
+        let (first, step) = (i128::from(self.first), i128::from(self.step));
+        let (other_first, other_step) = (i128::from(other.first), i128::from(other.step));
+        let (gcd, inverse) = gcd_and_inverse(step, other_step);
+        let offset = other_first - first;
+        if offset % gcd != 0 {
+            return None;
+        }
+        let modulus = (other_step / gcd) as u128;
+        let k = (offset / gcd).rem_euclid(modulus as i128) as u128 * inverse.rem_euclid(modulus as i128) as u128
+            % modulus;
+        let lcm = (step / gcd) as u128 * other_step as u128;
+        let aligned = first as u128 + step as u128 * k;
+
+        // Move the aligned unit to the first one that is in both ranges
+        let start = if aligned >= low {
+            low + (aligned - low) % lcm
+        } else {
+            low + (lcm - (low - aligned) % lcm) % lcm
+        };
+        if start > high {
+            return None;
+        }
+        let end = start + (high - start) / lcm * lcm;
+        let step = if start == end { 1 } else { lcm as u64 };
+        Some(self.stepped(start as u64, end as u64, step))
+    }
+
+    /// The units in `self` that are not in `other`, in the same order as in `self`.
+    /// Since `other` may be in the middle of `self`, there can be a part before `other`
+    /// and a part after it.
+    ///
+    /// If `other` has a larger step, the units of `self` that fall between the units of
+    /// `other` are left as well. Each gap between two units of `other` is usually left as
+    /// a range of its own, so that the units stay in order. When there would be more gaps
+    /// than units in each gap, the units in the gaps are left as ranges with the step of
+    /// `other` instead, one for each place in the gap, which takes fewer ranges.
+    pub fn difference(&self, other: &Self) -> Vec<Self> {
+        let common = match self.intersection(other) {
+            Some(common) => common,
+            None => return vec![self.clone()],
+        };
+        let mut parts = vec![];
+        if self.first < common.first {
+            parts.push(self.span(self.first, common.first - self.step));
+        }
+        // The number of units of `self` from one unit of `common` to the next
+        let per_gap = common.step / self.step;
+        let gaps = common.count() - 1;
+        if gaps > 0 && per_gap > 1 {
+            if gaps < per_gap {
+                for gap in 0..gaps {
+                    let start = common.first + gap * common.step;
+                    parts.push(self.span(start + self.step, start + common.step - self.step));
+                }
+            } else {
+                for place in 1..per_gap {
+                    let offset = place * self.step;
+                    parts.push(self.stepped(
+                        common.first + offset,
+                        common.last - common.step + offset,
+                        common.step,
+                    ));
+                }
+            }
+        }
+        if common.last < self.last {
+            parts.push(self.span(common.last + self.step, self.last));
+        }
+        parts
+    }
+}
+
+/// The greatest common divisor of `a` and `b`, and a number that `a` can be multiplied
+/// by to get the divisor, modulo `b`. Both numbers must be positive.
+fn gcd_and_inverse(a: i128, b: i128) -> (i128, i128) {
+    let (mut old_r, mut r) = (a, b);
+    let (mut old_s, mut s) = (1, 0);
+    while r != 0 {
+        let quotient = old_r / r;
+        let next_r = old_r - quotient * r;
+        old_r = r;
+        r = next_r;
+        let next_s = old_s - quotient * s;
+        old_s = s;
+        s = next_s;
+    }
+    (old_r, old_s)
 }
 
 impl fmt::Display for Range {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.first == self.last {
-            write!(f, "{}", self.first)
-        } else {
-            write!(f, "{}-{}", self.first, self.last)
+        let width = self.width;
+        write!(f, "{}{:0width$}", self.prefix, self.first, width = width)?;
+        if self.first != self.last {
+            let first_digits = format!("{:0width$}", self.first, width = width).len();
+            let last_digits = format!("{:0width$}", self.last, width = width).len();
+            if self.prefix.is_empty() || first_digits == last_digits {
+                write!(f, "-{:0width$}", self.last, width = width)?;
+            } else {
+                // `LT8-12` would be read as the single unit `LT8-12`, so the prefix has to
+                // be repeated: `LT8-LT12`
+                write!(f, "-{}{:0width$}", self.prefix, self.last, width = width)?;
+            }
+        }
+        if self.step > 1 {
+            write!(f, "/{}", self.step)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Fail, Debug, PartialEq)]
+pub enum RangeParseError {
+    #[fail(display = "Unit numbers must end in a digit")]
+    Invalid,
+
+    #[fail(display = "Number is too large")]
+    NumberTooLarge,
+
+    #[fail(display = "Range goes from high to low")]
+    DecreasingRange,
+
+    #[fail(display = "Step must be at least 1")]
+    ZeroStep,
+}
+
+/// Parses a single unit, like `LT123-0057`, into its prefix, number and width. The
+/// number is the digits at the end, and the prefix is everything before them.
+fn parse_unit(s: &str) -> Result<(&str, u64, usize), RangeParseError> {
+    let digits_start = s
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map(|idx| idx + s[idx..].chars().next().unwrap().len_utf8())
+        .unwrap_or(0);
+    let (prefix, digits) = s.split_at(digits_start);
+    if digits.is_empty() {
+        return Err(RangeParseError::Invalid);
+    }
+    let number = parse_number(digits)?;
+    Ok((prefix, number, padded_width(digits)))
+}
+
+/// Parses a string of digits, checking that it is no larger than `MAX_UNIT`
+pub(crate) fn parse_number(digits: &str) -> Result<u64, RangeParseError> {
+    match digits.parse() {
+        Ok(number) if number <= MAX_UNIT => Ok(number),
+        _ => Err(RangeParseError::NumberTooLarge),
+    }
+}
+
+/// The width a number was padded to, or zero if it wasn't padded
+pub(crate) fn padded_width(digits: &str) -> usize {
+    if digits.len() > 1 && digits.starts_with('0') {
+        digits.len()
+    } else {
+        0
+    }
+}
+
+/// Reads a single unit (`57`, `LT123-0057`) or a range of units. Ranges are written as
+/// the first unit followed by a hyphen and the last number (`1-50`, `LT123-0057-0072`),
+/// or by the first and last units in full (`LT8-LT12`).
+///
+/// Because prefixes may contain hyphens, a prefixed range written the short way must
+/// have the same number of digits on both sides of the hyphen. Otherwise, something
+/// like `LT123-0057` is read as a single unit.
+///
+/// A range can end with a step after a slash, like `1-99/2`.
+impl FromStr for Range {
+    type Err = RangeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(idx) = s.rfind('/') {
+            let step = &s[idx + 1..];
+            if !step.is_empty() && step.bytes().all(|b| b.is_ascii_digit()) {
+                let step = parse_number(step)?;
+                if step == 0 {
+                    return Err(RangeParseError::ZeroStep);
+                }
+                return Ok(s[..idx].parse::<Range>()?.with_step(step));
+            }
         }
+
+        for (idx, _) in s.rmatch_indices('-') {
+            let (left, right) = (&s[..idx], &s[idx + 1..]);
+            let (prefix, first, width) = match parse_unit(left) {
+                Ok(unit) => unit,
+                Err(RangeParseError::NumberTooLarge) => return Err(RangeParseError::NumberTooLarge),
+                Err(_) => continue,
+            };
+            let last = if !right.is_empty() && right.bytes().all(|b| b.is_ascii_digit()) {
+                let first_digits = left.len() - prefix.len();
+                if !prefix.is_empty() && first_digits != right.len() {
+                    continue;
+                }
+                parse_number(right)?
+            } else {
+                match parse_unit(right) {
+                    Ok((last_prefix, last, _)) if last_prefix == prefix => last,
+                    Err(RangeParseError::NumberTooLarge) => return Err(RangeParseError::NumberTooLarge),
+                    _ => continue,
+                }
+            };
+            if first > last {
+                return Err(RangeParseError::DecreasingRange);
+            }
+            return Ok(Range::with_prefix(prefix.into(), first, last, width));
+        }
+
+        let (prefix, number, width) = parse_unit(s)?;
+        Ok(Range::with_prefix(prefix.into(), number, number, width))
     }
 }
 
+/// Sorts `ranges` and merges the ones that overlap or are next to each other, so that
+/// the same units always end up as the same list of ranges.
+///
+/// Ranges with a step are only merged with ranges that have the same step, so the odd
+/// and even units from 1 to 100 stay as `1-99/2, 2-100/2` rather than becoming `1-100`.
+pub(crate) fn normalize(ranges: &[Range]) -> Vec<Range> {
+    let mut sorted = ranges.to_vec();
+    sorted.sort_by(|a, b| (&a.prefix, a.first, a.step).cmp(&(&b.prefix, b.first, b.step)));
+    if sorted.iter().all(|range| range.step == 1 && range.width == sorted[0].width) {
+        return merge_sorted(sorted);
+    }
+
+    // A range with a step can have units in between the units of any of the other
+    // ranges, not just the one before it, and ranges padded to different widths can
+    // overlap without being merged. Every range has the units that are already
+    // in an earlier range taken out of it, and then the pieces are merged.
+    let mut disjoint: Vec<Range> = Vec::with_capacity(sorted.len());
+    for range in sorted {
+        let pieces = disjoint.iter().fold(vec![range], |pieces, earlier| {
+            pieces.iter().flat_map(|piece| piece.difference(earlier)).collect()
+        });
+        disjoint.extend(pieces);
+    }
+    disjoint.sort_by(|a, b| (&a.prefix, a.first, a.step).cmp(&(&b.prefix, b.first, b.step)));
+    let mut normalized: Vec<Range> = Vec::with_capacity(disjoint.len());
+    'ranges: for range in disjoint {
+        for earlier in normalized.iter_mut().rev() {
+            if let Some(merged) = earlier.merge(&range) {
+                *earlier = merged;
+                continue 'ranges;
+            }
+        }
+        normalized.push(range);
+    }
+    normalized
+}
+
+/// Merges sorted ranges that overlap or are next to each other. Only works for ranges
+/// without a step, where a range can only overlap the ranges right before it.
+fn merge_sorted(sorted: Vec<Range>) -> Vec<Range> {
+    let mut normalized: Vec<Range> = Vec::with_capacity(sorted.len());
+    for range in sorted {
+        if let Some(last) = normalized.last_mut() {
+            if let Some(merged) = last.merge(&range) {
+                *last = merged;
+                continue;
+            }
+        }
+        normalized.push(range);
+    }
+    normalized
+}
+
+/// The number of units in all of `ranges`, which is at most `u64::MAX`. Ranges with
+/// different prefixes can add up to more than that, in which case it stops there.
+pub(crate) fn total(ranges: &[Range]) -> u64 {
+    ranges.iter().fold(0, |total, range| total.saturating_add(range.count()))
+}
+
+/// Removes the units in `set` from `ranges`, keeping the rest in the same order.
+pub(crate) fn subtract(ranges: &[Range], set: &RangeSet) -> Vec<Range> {
+    let mut result = ranges.to_vec();
+    for range in set.ranges() {
+        result = result
+            .iter()
+            .flat_map(|r| r.difference(range))
+            .collect();
+    }
+    result
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -87,6 +455,49 @@ mod tests {
         assert_eq!(range.count(), 1);
     }
 
+    #[test]
+    fn count_of_everything() {
+        let range = Range::new(0, MAX_UNIT);
+        assert_eq!(range.count(), u64::MAX);
+        let range = Range::num(MAX_UNIT);
+        assert_eq!(range.count(), 1);
+    }
+
+    #[test]
+    fn total_saturates() {
+        let ranges = vec![
+            Range::with_prefix("A".into(), 0, MAX_UNIT, 0),
+            Range::with_prefix("B".into(), 0, MAX_UNIT, 0),
+        ];
+        assert_eq!(total(&ranges), u64::MAX);
+        assert_eq!(total(&[Range::new(1, 10), Range::num(20)]), 11);
+    }
+
+    #[test]
+    fn split_at_extremes() {
+        let range = Range::new(0, MAX_UNIT);
+        assert_eq!(
+            range.split(u64::MAX - 1),
+            (Some(Range::new(0, MAX_UNIT - 1)), Some(Range::num(MAX_UNIT)), 0)
+        );
+        assert_eq!(range.split(u64::MAX), (Some(range.clone()), None, 0));
+        assert_eq!(range.difference(&Range::num(MAX_UNIT)), vec![Range::new(0, MAX_UNIT - 1)]);
+        assert_eq!(Range::num(MAX_UNIT).merge(&Range::num(MAX_UNIT)), Some(Range::num(MAX_UNIT)));
+    }
+
+    #[test]
+    fn parse_largest() {
+        assert_eq!("18446744073709551614".parse(), Ok(Range::num(MAX_UNIT)));
+        assert_eq!(
+            "18446744073709551615".parse::<Range>(),
+            Err(RangeParseError::NumberTooLarge)
+        );
+        assert_eq!(
+            "LT0-LT18446744073709551615".parse::<Range>(),
+            Err(RangeParseError::NumberTooLarge)
+        );
+    }
+
     #[test]
     fn split() {
         let range = Range::new(1, 10);
@@ -96,6 +507,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn intersection() {
+        let range = Range::new(1, 10);
+        assert_eq!(range.intersection(&Range::new(5, 20)), Some(Range::new(5, 10)));
+        assert_eq!(range.intersection(&Range::new(11, 20)), None);
+    }
+
+    #[test]
+    fn difference() {
+        let range = Range::new(1, 10);
+        assert_eq!(
+            range.difference(&Range::new(4, 6)),
+            vec![Range::new(1, 3), Range::new(7, 10)]
+        );
+        assert_eq!(range.difference(&Range::new(0, 6)), vec![Range::new(7, 10)]);
+        assert_eq!(range.difference(&Range::new(1, 10)), vec![]);
+    }
+
     #[test]
     fn format_single() {
         let range = Range::new(696, 696);
@@ -107,4 +536,188 @@ mod tests {
         let range = Range::new(1, 10);
         assert_eq!(format!("{}", range), "1-10");
     }
+
+    #[test]
+    fn format_prefixed() {
+        let range = Range::with_prefix("LT123-".into(), 57, 72, 4);
+        assert_eq!(format!("{}", range), "LT123-0057-0072");
+        let range = Range::with_prefix("A".into(), 8, 12, 0);
+        assert_eq!(format!("{}", range), "A8-A12");
+        let range = Range::with_prefix("".into(), 7, 7, 3);
+        assert_eq!(format!("{}", range), "007");
+    }
+
+    #[test]
+    fn parse_prefixed() {
+        assert_eq!(
+            "LT123-0057-0072".parse(),
+            Ok(Range::with_prefix("LT123-".into(), 57, 72, 4))
+        );
+        assert_eq!(
+            "LT123-0057".parse(),
+            Ok(Range::with_prefix("LT123-".into(), 57, 57, 4))
+        );
+        assert_eq!("A8-A12".parse(), Ok(Range::with_prefix("A".into(), 8, 12, 0)));
+        assert_eq!("007-012".parse(), Ok(Range::with_prefix("".into(), 7, 12, 3)));
+        assert_eq!("1-50".parse(), Ok(Range::new(1, 50)));
+        assert_eq!("LT9-LT5".parse::<Range>(), Err(RangeParseError::DecreasingRange));
+        assert_eq!("LT".parse::<Range>(), Err(RangeParseError::Invalid));
+    }
+
+    #[test]
+    fn prefixed_round_trip() {
+        for text in &["LT123-0057-0072", "LT123-0057", "A8-A12", "A08-12", "0001-1000"] {
+            let range: Range = text.parse().unwrap();
+            assert_eq!(&format!("{}", range), text);
+        }
+    }
+
+    #[test]
+    fn normalize_ranges() {
+        let ranges = vec![
+            Range::new(11, 20),
+            Range::with_prefix("A".into(), 1, 2, 0),
+            Range::new(1, 5),
+            Range::new(6, 10),
+            Range::new(15, 30),
+            Range::num(40),
+        ];
+        assert_eq!(
+            normalize(&ranges),
+            vec![Range::new(1, 30), Range::num(40), Range::with_prefix("A".into(), 1, 2, 0)]
+        );
+    }
+
+    #[test]
+    fn different_prefixes_dont_overlap() {
+        let a = Range::with_prefix("A".into(), 1, 10, 0);
+        let b = Range::with_prefix("B".into(), 1, 10, 0);
+        assert!(!a.overlaps(&b));
+        assert_eq!(a.difference(&b), vec![a.clone()]);
+    }
+
+    #[test]
+    fn stepped() {
+        let range = Range::new(1, 100).with_step(2);
+        assert_eq!(range, Range::new(1, 99).with_step(2));
+        assert_eq!(range.count(), 50);
+        assert_eq!(Range::new(5, 6).with_step(2), Range::num(5));
+        assert_eq!(
+            range.split(10),
+            (Some(Range::new(1, 19).with_step(2)), Some(Range::new(21, 99).with_step(2)), 0)
+        );
+        assert_eq!(Range::new(0, MAX_UNIT).with_step(2).count(), u64::MAX / 2 + 1);
+    }
+
+    #[test]
+    fn stepped_overlaps() {
+        let odd = Range::new(1, 99).with_step(2);
+        let even = Range::new(2, 100).with_step(2);
+        assert!(!odd.overlaps(&even));
+        assert_eq!(odd.intersection(&even), None);
+        assert_eq!(odd.intersection(&Range::new(10, 20)), Some(Range::new(11, 19).with_step(2)));
+        assert_eq!(
+            odd.intersection(&Range::new(0, 100).with_step(3)),
+            Some(Range::new(3, 99).with_step(6))
+        );
+        assert_eq!(Range::new(1, 10).with_step(3).intersection(&Range::new(4, 20).with_step(6)), Some(Range::new(4, 10).with_step(6)));
+        assert_eq!(odd.intersection(&Range::num(50)), None);
+    }
+
+    #[test]
+    fn stepped_difference() {
+        let range = Range::new(1, 20);
+        assert_eq!(
+            range.difference(&Range::new(4, 13).with_step(3)),
+            vec![
+                Range::new(1, 3),
+                Range::new(5, 11).with_step(3),
+                Range::new(6, 12).with_step(3),
+                Range::new(14, 20),
+            ]
+        );
+        let odd = Range::new(1, 99).with_step(2);
+        assert_eq!(
+            odd.difference(&Range::new(5, 95)),
+            vec![Range::new(1, 3).with_step(2), Range::new(97, 99).with_step(2)]
+        );
+        assert_eq!(
+            Range::new(1, 30).difference(&Range::new(1, 30).with_step(10)),
+            vec![Range::new(2, 10), Range::new(12, 20), Range::new(22, 30)]
+        );
+        assert_eq!(
+            Range::new(1, 99).with_step(2).difference(&Range::new(1, 99).with_step(6)),
+            vec![Range::new(3, 93).with_step(6), Range::new(5, 95).with_step(6), Range::num(99)]
+        );
+    }
+
+    #[test]
+    fn stepped_difference_range_count() {
+        let range = Range::new(1, 2_000_000);
+        assert_eq!(range.difference(&range.clone().with_step(20_000)).len(), 100);
+        assert_eq!(
+            range.difference(&range.clone().with_step(2)),
+            vec![Range::new(2, 1_999_998).with_step(2), Range::num(2_000_000)]
+        );
+        assert_eq!(range.difference(&range.clone().with_step(3)).len(), 3);
+        let everything = Range::new(0, MAX_UNIT);
+        assert_eq!(
+            everything.difference(&everything.clone().with_step(1 << 62)).len(),
+            4
+        );
+    }
+
+    #[test]
+    fn merge_widths() {
+        let padded = Range::with_prefix(String::new(), 7, 7, 3);
+        assert_eq!(padded.merge(&Range::num(8)), None);
+        assert_eq!(
+            padded.merge(&Range::with_prefix(String::new(), 8, 9, 3)),
+            Some(Range::with_prefix(String::new(), 7, 9, 3))
+        );
+        assert_eq!(
+            normalize(&[padded.clone(), Range::num(8)]),
+            vec![padded, Range::num(8)]
+        );
+        assert_eq!(
+            normalize(&[Range::new(5, 12), Range::with_prefix(String::new(), 1, 10, 3)]),
+            vec![Range::with_prefix(String::new(), 1, 10, 3), Range::new(11, 12)]
+        );
+    }
+
+    #[test]
+    fn stepped_merge() {
+        let odd = Range::new(1, 9).with_step(2);
+        assert_eq!(odd.merge(&Range::num(11)), Some(Range::new(1, 11).with_step(2)));
+        assert_eq!(odd.merge(&Range::new(7, 15).with_step(2)), Some(Range::new(1, 15).with_step(2)));
+        assert_eq!(odd.merge(&Range::num(12)), None);
+        assert_eq!(odd.merge(&Range::new(10, 20)), None);
+        assert_eq!(Range::num(1).merge(&Range::num(3)), None);
+    }
+
+    #[test]
+    fn format_and_parse_stepped() {
+        assert_eq!(format!("{}", Range::new(1, 100).with_step(2)), "1-99/2");
+        assert_eq!(format!("{}", Range::with_prefix("LT".into(), 8, 20, 0).with_step(4)), "LT8-LT20/4");
+        for text in &["1-99/2", "LT123-0001-0097/3", "A8-A20/4"] {
+            let range: Range = text.parse().unwrap();
+            assert_eq!(&format!("{}", range), text);
+        }
+        assert_eq!("1-10/0".parse::<Range>(), Err(RangeParseError::ZeroStep));
+    }
+
+    #[test]
+    fn normalize_stepped() {
+        let ranges = vec![
+            Range::new(2, 100).with_step(2),
+            Range::new(1, 9).with_step(2),
+            Range::num(11),
+            Range::new(4, 8),
+            Range::new(13, 19).with_step(2),
+        ];
+        assert_eq!(
+            normalize(&ranges),
+            vec![Range::new(1, 19).with_step(2), Range::new(2, 100).with_step(2)]
+        );
+    }
 }
@@ -0,0 +1,228 @@
+use crate::range::{self, Range};
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{BitAnd, BitOr, BitXor, Sub};
+
+/// A set of units, stored as a sorted list of ranges that don't overlap or touch.
+///
+/// Unlike the ranges of a `Group`, which keep the order they were written in, a set
+/// only cares about which units are in it. `1-5, 6-10` and `6-10, 1-5` are the same
+/// set as `1-10`. Units with different prefixes are different units.
+///
+/// Ranges with a step are only merged with ranges that have the same step, so the same
+/// units can be in two sets as different ranges: `1-99/2, 2-100/2` has the same units
+/// as `1-100`. Sets are compared by their units, so those two sets are equal.
+#[derive(Clone, Debug, Default)]
+pub struct RangeSet {
+    ranges: Vec<Range>,
+}
+
+impl RangeSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The ranges of the set, sorted by prefix and then by number
+    pub fn ranges(&self) -> &[Range] {
+        &self.ranges
+    }
+
+    pub fn into_ranges(self) -> Vec<Range> {
+        self.ranges
+    }
+
+    /// The number of units in the set, or `u64::MAX` if there are more than that
+    pub fn len(&self) -> u64 {
+        range::total(&self.ranges)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Whether every unit in `range` is in the set
+    pub fn contains(&self, range: &Range) -> bool {
+        range::subtract(std::slice::from_ref(range), self).is_empty()
+    }
+
+    /// Whether every unit in `self` is also in `other`
+    pub fn is_subset(&self, other: &RangeSet) -> bool {
+        self.ranges.iter().all(|r| other.contains(r))
+    }
+
+    /// Every unit in the set as ranges of one unit each, range by range
+    pub fn units(&self) -> impl Iterator<Item = Range> + '_ {
+        self.ranges.iter().flat_map(|r| {
+            (0..r.count())
+                .map(move |i| r.first() + i * r.step())
+                .map(move |n| Range::with_prefix(r.prefix().into(), n, n, r.width()))
+        })
+    }
+
+    /// The units that are in `self`, `other`, or both
+    pub fn union(&self, other: &RangeSet) -> RangeSet {
+        self.ranges.iter().chain(&other.ranges).cloned().collect()
+    }
+
+    /// The units that are in both `self` and `other`
+    pub fn intersection(&self, other: &RangeSet) -> RangeSet {
+        self.ranges
+            .iter()
+            .flat_map(|a| other.ranges.iter().filter_map(move |b| a.intersection(b)))
+            .collect()
+    }
+
+    /// The units in `self` that are not in `other`
+    pub fn difference(&self, other: &RangeSet) -> RangeSet {
+        range::subtract(&self.ranges, other).into_iter().collect()
+    }
+
+    /// The units that are in either `self` or `other`, but not both
+    pub fn symmetric_difference(&self, other: &RangeSet) -> RangeSet {
+        self.difference(other).union(&other.difference(self))
+    }
+}
+
+impl PartialEq for RangeSet {
+    fn eq(&self, other: &RangeSet) -> bool {
+        self.ranges == other.ranges || (self.is_subset(other) && other.is_subset(self))
+    }
+}
+
+impl Eq for RangeSet {}
+
+impl FromIterator<Range> for RangeSet {
+    fn from_iter<I: IntoIterator<Item = Range>>(iter: I) -> Self {
+        let ranges: Vec<Range> = iter.into_iter().collect();
+        RangeSet {
+            ranges: range::normalize(&ranges),
+        }
+    }
+}
+
+impl From<Range> for RangeSet {
+    fn from(range: Range) -> Self {
+        RangeSet { ranges: vec![range] }
+    }
+}
+
+impl<'a> From<&'a [Range]> for RangeSet {
+    fn from(ranges: &'a [Range]) -> Self {
+        RangeSet {
+            ranges: range::normalize(ranges),
+        }
+    }
+}
+
+impl fmt::Display for RangeSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, range) in self.ranges.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", range)?;
+        }
+        Ok(())
+    }
+}
+
+macro_rules! impl_set_op {
+    ($trait:ident, $method:ident, $set_method:ident) => {
+        impl<'a, 'b> $trait<&'b RangeSet> for &'a RangeSet {
+            type Output = RangeSet;
+
+            fn $method(self, other: &'b RangeSet) -> RangeSet {
+                self.$set_method(other)
+            }
+        }
+
+        impl $trait<RangeSet> for RangeSet {
+            type Output = RangeSet;
+
+            fn $method(self, other: RangeSet) -> RangeSet {
+                self.$set_method(&other)
+            }
+        }
+    };
+}
+
+impl_set_op!(BitOr, bitor, union);
+impl_set_op!(BitAnd, bitand, intersection);
+impl_set_op!(Sub, sub, difference);
+impl_set_op!(BitXor, bitxor, symmetric_difference);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(ranges: &[(u64, u64)]) -> RangeSet {
+        ranges.iter().map(|&(first, last)| Range::new(first, last)).collect()
+    }
+
+    #[test]
+    fn normalized() {
+        assert_eq!(set(&[(6, 10), (1, 5), (20, 30), (25, 26)]).ranges(), set(&[(1, 10), (20, 30)]).ranges());
+        assert_eq!(set(&[(6, 10), (1, 5)]).ranges(), &[Range::new(1, 10)]);
+    }
+
+    #[test]
+    fn operators() {
+        let a = set(&[(1, 10), (20, 30)]);
+        let b = set(&[(5, 25)]);
+        assert_eq!(&a | &b, set(&[(1, 30)]));
+        assert_eq!(&a & &b, set(&[(5, 10), (20, 25)]));
+        assert_eq!(&a - &b, set(&[(1, 4), (26, 30)]));
+        assert_eq!(&a ^ &b, set(&[(1, 4), (11, 19), (26, 30)]));
+        assert_eq!(a.clone() - a, RangeSet::new());
+    }
+
+    #[test]
+    fn containment() {
+        let a = set(&[(1, 10), (20, 30)]);
+        assert!(a.contains(&Range::new(2, 5)));
+        assert!(!a.contains(&Range::new(8, 21)));
+        assert!(set(&[(3, 4), (25, 30)]).is_subset(&a));
+        assert!(!set(&[(3, 4), (31, 31)]).is_subset(&a));
+    }
+
+    #[test]
+    fn len_and_units() {
+        let a = set(&[(1, 3), (7, 8)]);
+        assert_eq!(a.len(), 5);
+        let units: Vec<u64> = a.units().map(|unit| unit.first()).collect();
+        assert_eq!(units, vec![1, 2, 3, 7, 8]);
+    }
+
+    #[test]
+    fn prefixes() {
+        let a: RangeSet = vec![Range::with_prefix("A".into(), 1, 10, 0), Range::new(1, 10)]
+            .into_iter()
+            .collect();
+        let b = RangeSet::from(Range::with_prefix("A".into(), 5, 20, 0));
+        assert_eq!(
+            (&a & &b).ranges(),
+            &[Range::with_prefix("A".into(), 5, 10, 0)]
+        );
+        assert_eq!(format!("{}", &a - &b), "1-10, A1-4");
+    }
+
+    #[test]
+    fn stepped() {
+        let odd = RangeSet::from(Range::new(1, 99).with_step(2));
+        let even = RangeSet::from(Range::new(2, 100).with_step(2));
+        let all = set(&[(1, 100)]);
+        assert!((&odd & &even).is_empty());
+        assert!(all.is_subset(&(&odd | &even)));
+        assert!((&odd | &even).is_subset(&all));
+        assert_eq!(&all - &even, odd);
+        assert_eq!(&odd | &even, all);
+        assert_ne!(odd.ranges(), all.ranges());
+        assert_ne!(odd, even);
+        assert_eq!((&odd - &set(&[(1, 90)])).len(), 5);
+        let units: Vec<u64> = (&odd & &set(&[(5, 10)])).units().map(|unit| unit.first()).collect();
+        assert_eq!(units, vec![5, 7, 9]);
+        let huge_step = RangeSet::from(Range::new(0, range::MAX_UNIT).with_step(1 << 62));
+        let units: Vec<u64> = huge_step.units().map(|unit| unit.first()).collect();
+        assert_eq!(units, vec![0, 1 << 62, 2 << 62, 3 << 62]);
+    }
+}
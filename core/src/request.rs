@@ -1,19 +1,224 @@
+use crate::group::{self, Name};
+use crate::range::Range;
+use std::fmt;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Request {
     name: String,
-    amounts: Vec<u32>,
+    amounts: Vec<Amount>,
+    source: Option<Source>,
+    comment: Option<String>,
 }
 
 impl Request {
-    pub fn new(name: String, amounts: Vec<u32>) -> Self {
-        Self { name, amounts }
+    pub fn new(name: String, amounts: Vec<Amount>) -> Self {
+        Self {
+            name,
+            amounts,
+            source: None,
+            comment: None,
+        }
+    }
+
+    /// A request that reuses every unit given to the request named `source`, like
+    /// `HAST: same as TC`
+    pub fn same_as(name: String, source: String) -> Self {
+        Self::new(name, vec![]).with_source(Source::SameAs(source))
+    }
+
+    /// Takes the request's units from the units given to another request instead of
+    /// from the inventory
+    pub fn with_source(self, source: Source) -> Self {
+        Self {
+            source: Some(source),
+            ..self
+        }
+    }
+
+    /// Attaches a comment to the request, like the `# for Alice` at the end of
+    /// `A: 10, 5 # for Alice`
+    pub fn with_comment(self, comment: String) -> Self {
+        Self {
+            comment: Some(comment),
+            ..self
+        }
     }
 
     pub fn name(&self) -> &str {
         &self.name
     }
 
-    pub fn amounts(&self) -> &[u32] {
+    pub fn amounts(&self) -> &[Amount] {
         &self.amounts
     }
+
+    pub fn source(&self) -> Option<&Source> {
+        self.source.as_ref()
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+}
+
+/// Writes the request in the requests notation, like `A: 10, CTRL=5`. Unnamed amounts
+/// that are the same as the ones after them are written as a repetition, like `32x3`.
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_request_name(f, &self.name)?;
+        write!(f, ":")?;
+        if let Some(Source::SameAs(ref source)) = self.source {
+            write!(f, " same as ")?;
+            return write_request_name(f, source);
+        }
+
+        let mut idx = 0;
+        while idx < self.amounts.len() {
+            let amount = &self.amounts[idx];
+            let repeats = self.amounts[idx..]
+                .iter()
+                .take_while(|other| *other == amount)
+                .count();
+            let can_repeat = match amount.quantity {
+                Quantity::Skip | Quantity::Units(_) => false,
+                _ => amount.group.is_none(),
+            };
+            if idx > 0 {
+                write!(f, ",")?;
+            }
+            if can_repeat && repeats > 1 {
+                write!(f, " {}x{}", amount, repeats)?;
+                idx += repeats;
+            } else {
+                write!(f, " {}", amount)?;
+                idx += 1;
+            }
+        }
+        if let Some(Source::From(ref source)) = self.source {
+            write!(f, " from ")?;
+            write_request_name(f, source)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a request name, in quotes if it needs them. Request names can have single
+/// spaces between words without being quoted.
+fn write_request_name(f: &mut fmt::Formatter, name: &str) -> fmt::Result {
+    let needs_quotes = name.is_empty()
+        || name.starts_with(' ')
+        || name.ends_with(' ')
+        || name.contains("  ")
+        || name.chars().any(|c| (c.is_whitespace() && c != ' ') || ":,#\"\\".contains(c));
+    if needs_quotes {
+        group::write_quoted(f, name)
+    } else {
+        write!(f, "{}", name)
+    }
+}
+
+/// Where a request gets its units when it reuses the units of another request, for
+/// tests that are run one after the other on the same parts
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    /// Every unit given to the named request, like `HAST: same as TC`
+    SameAs(String),
+    /// The request's amounts, taken out of the units given to the named request
+    /// instead of the inventory, like `HAST: 40 from TC`
+    From(String),
+}
+
+impl Source {
+    /// The name of the request whose units are reused
+    pub fn request_name(&self) -> &str {
+        match self {
+            Source::SameAs(name) | Source::From(name) => name,
+        }
+    }
+}
+
+/// The number of units a request wants from a single group.
+///
+/// Amounts without a group name apply to the group at the same position in the
+/// inventory, counting only the other unnamed amounts of the request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Amount {
+    group: Option<String>,
+    quantity: Quantity,
+}
+
+impl Amount {
+    pub fn new(count: u64) -> Self {
+        Self::with_quantity(None, Quantity::Count(count))
+    }
+
+    /// An amount for the group called `group`, regardless of where it is in the inventory
+    pub fn named(group: String, count: u64) -> Self {
+        Self::with_quantity(Some(group), Quantity::Count(count))
+    }
+
+    /// A placeholder for a group that the request doesn't want any units from
+    pub fn skip() -> Self {
+        Self::with_quantity(None, Quantity::Skip)
+    }
+
+    pub fn with_quantity(group: Option<String>, quantity: Quantity) -> Self {
+        Self { group, quantity }
+    }
+
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    pub fn quantity(&self) -> &Quantity {
+        &self.quantity
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Quantity {
+    /// The group is not requested at all, and will be left out of the filled request.
+    /// This is different from `Count(0)`, which still lists the group.
+    Skip,
+    Count(u64),
+    /// A percentage of the units in the group
+    Percent(u32),
+    /// A fraction of the units in the group, as a numerator and a denominator. The
+    /// denominator is never zero.
+    Fraction(u32, u32),
+    /// Whatever is left in the group once every other request has been filled
+    Rest,
+    /// These exact units, which are taken out of the group before anything else
+    Units(Vec<Range>),
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.group {
+            Some(ref group) => write!(f, "{}={}", Name(group), self.quantity),
+            None => write!(f, "{}", self.quantity),
+        }
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Quantity::Skip => write!(f, "-"),
+            Quantity::Count(count) => write!(f, "{}", count),
+            Quantity::Percent(percent) => write!(f, "{}%", percent),
+            Quantity::Fraction(numerator, denominator) => write!(f, "{}/{}", numerator, denominator),
+            Quantity::Rest => write!(f, "*"),
+            Quantity::Units(ranges) => {
+                write!(f, "[")?;
+                for (idx, range) in ranges.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", range)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
 }
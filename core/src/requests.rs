@@ -1,5 +1,12 @@
-use crate::request::Request;
+use crate::group;
+use crate::inventory::{trailing_comment, OwnedSpan};
+use crate::range::{self, Range, RangeParseError};
+use crate::request::{Amount, Quantity, Request, Source};
+use crate::split;
 use pest::Parser;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
 
 #[derive(Parser)]
 #[grammar = "requests.pest"]
@@ -8,52 +15,369 @@ pub struct RequestsParser;
 pub type RequestsParseResult = Result<Vec<Request>, Vec<RequestsParseError>>;
 
 pub fn parse(input: &str) -> RequestsParseResult {
+    parse_with(input, &ParseOptions::default())
+}
+
+pub fn parse_with(input: &str, options: &ParseOptions) -> RequestsParseResult {
     let mut parse = RequestsParser::parse(Rule::requests, input).map_err(|x| vec![x.into()])?;
     let requests = parse
         .next()
         .expect("If there is no input, SyntaxError is returned in the above statement");
 
-    let mut requests_data = vec![];
+    let mut requests_data: Vec<ParsedRequest> = vec![];
+    let mut request_spans: HashMap<String, (::pest::Span, usize)> = HashMap::new();
+    let mut errors = vec![];
+    // Units pinned without brackets at the end of the last request, like the `LT5` in
+    // `A: 3 LT5`, which could also be the start of the next request's name
+    let mut bare_pinned: Option<::pest::Span> = None;
     for request in requests.into_inner() {
         match request.as_rule() {
             Rule::request => {
+                if let Some(units) = bare_pinned.take() {
+                    if !input[units.end()..request.as_span().start()].contains('\n') {
+                        errors.push(RequestsParseError::UnseparatedPinnedUnits { units: (&units).into() });
+                    }
+                }
+                if let Some(last) = request.clone().into_inner().last() {
+                    if last.as_rule() == Rule::pinned && !last.as_str().starts_with('[') {
+                        bare_pinned = Some(last.as_span());
+                    }
+                }
+                // Every amount rule ends in a token, so the span of the last amount ends
+                // where the request does, unlike the span of the request itself
+                let request_end = request.clone().into_inner().last().unwrap().as_span().end();
+                let comment = trailing_comment(input, request_end);
                 let mut inner = request.into_inner();
-                let name = inner.next().unwrap().as_str().into();
+                let name_pair = inner.next().unwrap();
+                let name = group::unquote(name_pair.as_str());
 
                 let mut amounts = vec![];
+                let mut source = None;
 
                 for amount_parse in inner {
+                    let span = amount_parse.as_span();
                     match amount_parse.as_rule() {
-                        Rule::number => {
-                            let num = amount_parse.as_str().parse().unwrap();
-                            amounts.push(num);
+                        Rule::same_as => {
+                            let source_name = amount_parse.into_inner().next().unwrap();
+                            source = Some(Source::SameAs(group::unquote(source_name.as_str())));
+                        }
+                        Rule::from => {
+                            let source_name = amount_parse.into_inner().next().unwrap();
+                            source = Some(Source::From(group::unquote(source_name.as_str())));
+                        }
+                        Rule::named_amount => {
+                            let mut inner = amount_parse.into_inner();
+                            let group = group::unquote(inner.next().unwrap().as_str());
+                            let quantity = match parse_quantity(inner.next().unwrap(), &mut errors) {
+                                Ok(q) => q,
+                                Err(()) => continue,
+                            };
+                            amounts.push((Amount::with_quantity(Some(group), quantity), span));
                         }
                         Rule::repetition => {
                             let mut inner = amount_parse.into_inner();
-                            let num = inner.next().unwrap().as_str().parse().unwrap();
-                            let num_repeat = inner.next().unwrap().as_str().parse().unwrap();
-                            for _i in 0..num_repeat {
-                                amounts.push(num);
+                            let quantity = parse_quantity(inner.next().unwrap(), &mut errors);
+                            let num_repeat: Result<u32, ()> = parse_number(&inner.next().unwrap(), &mut errors);
+                            if num_repeat == Ok(0) {
+                                errors.push(RequestsParseError::ZeroRepetition {
+                                    repetition: (&span).into(),
+                                });
+                                continue;
+                            }
+                            if let (Ok(quantity), Ok(num_repeat)) = (quantity, num_repeat) {
+                                for _i in 0..num_repeat {
+                                    amounts.push((Amount::with_quantity(None, quantity.clone()), span.clone()));
+                                }
+                            }
+                        }
+                        _ => {
+                            if let Ok(quantity) = parse_quantity(amount_parse, &mut errors) {
+                                amounts.push((Amount::with_quantity(None, quantity), span));
                             }
                         }
-                        _ => unreachable!(),
                     }
                 }
 
-                requests_data.push(Request::new(name, amounts));
+                // Test if another request with this name has been defined. Requests that
+                // reuse different units can't be merged.
+                match request_spans.get(&name) {
+                    Some((_, idx)) if options.merge_duplicates && requests_data[*idx].source == source => {
+                        let first = &mut requests_data[*idx];
+                        merge_amounts(&mut first.amounts, amounts, &mut errors);
+                        if first.comment.is_none() {
+                            first.comment = comment;
+                        }
+                    }
+                    Some((first_request_span, _)) => errors.push(RequestsParseError::DuplicateRequest {
+                        first: first_request_span.into(),
+                        duplicate: (&name_pair.as_span()).into(),
+                    }),
+                    None => {
+                        request_spans.insert(name.clone(), (name_pair.as_span(), requests_data.len()));
+                        requests_data.push(ParsedRequest {
+                            name,
+                            amounts,
+                            source,
+                            comment,
+                        });
+                    }
+                }
             }
             Rule::EOI => {}
             _ => unreachable!(),
         }
     }
 
-    Ok(requests_data)
+    if !errors.is_empty() {
+        Err(errors)
+    } else {
+        Ok(requests_data
+            .into_iter()
+            .map(|parsed| {
+                let amounts = parsed.amounts.into_iter().map(|(amount, _)| amount).collect();
+                let mut request = Request::new(parsed.name, amounts);
+                if let Some(source) = parsed.source {
+                    request = request.with_source(source);
+                }
+                match parsed.comment {
+                    Some(comment) => request.with_comment(comment),
+                    None => request,
+                }
+            })
+            .collect())
+    }
+}
+
+/// A request whose amounts are kept with their spans until the end of parsing, in case
+/// they need to be merged with a request of the same name
+struct ParsedRequest<'i> {
+    name: String,
+    amounts: Vec<(Amount, ::pest::Span<'i>)>,
+    source: Option<Source>,
+    comment: Option<String>,
+}
+
+#[derive(Default)]
+pub struct ParseOptions {
+    /// When `true`, requests with the same name are combined into the first one by
+    /// adding their amounts together, instead of failing with
+    /// `RequestsParseError::DuplicateRequest`.
+    pub merge_duplicates: bool,
+}
+
+/// Adds `amounts` to the amounts of a request with the same name. Named amounts are
+/// added to the amount for the same group, and unnamed amounts to the unnamed amount
+/// at the same position. Amounts with nothing to be added to are appended.
+///
+/// Amounts that can't be added together are left as they were, and an error is
+/// appended to `errors`.
+fn merge_amounts<'i>(
+    into: &mut Vec<(Amount, ::pest::Span<'i>)>,
+    amounts: Vec<(Amount, ::pest::Span<'i>)>,
+    errors: &mut Vec<RequestsParseError>,
+) {
+    let mut position = 0;
+    for (amount, span) in amounts {
+        let existing = match amount.group() {
+            Some(group) => into.iter().position(|(a, _)| a.group() == Some(group)),
+            None => {
+                position += 1;
+                into.iter()
+                    .enumerate()
+                    .filter(|(_, (a, _))| a.group().is_none())
+                    .map(|(idx, _)| idx)
+                    .nth(position - 1)
+            }
+        };
+        let idx = match existing {
+            Some(idx) => idx,
+            None => {
+                into.push((amount, span));
+                continue;
+            }
+        };
+        match merge_quantities(into[idx].0.quantity(), amount.quantity()) {
+            Some(quantity) => into[idx].0 = Amount::with_quantity(amount.group().map(String::from), quantity),
+            None => errors.push(RequestsParseError::UnmergeableAmounts {
+                first: (&into[idx].1).into(),
+                duplicate: (&span).into(),
+            }),
+        }
+    }
+}
+
+/// Adds two quantities together, if they are of kinds that can be added. Skipping a
+/// group adds nothing, counts add to counts and shares to shares, and pinned units
+/// are pinned together.
+fn merge_quantities(first: &Quantity, second: &Quantity) -> Option<Quantity> {
+    match (first, second) {
+        (Quantity::Skip, quantity) | (quantity, Quantity::Skip) => Some(quantity.clone()),
+        (Quantity::Count(a), Quantity::Count(b)) => Some(Quantity::Count(a.saturating_add(*b))),
+        (Quantity::Percent(a), Quantity::Percent(b)) => a.checked_add(*b).map(Quantity::Percent),
+        (Quantity::Units(a), Quantity::Units(b)) => Some(Quantity::Units(a.iter().chain(b).cloned().collect())),
+        (Quantity::Percent(_), Quantity::Fraction(..))
+        | (Quantity::Fraction(..), Quantity::Percent(_))
+        | (Quantity::Fraction(..), Quantity::Fraction(..)) => {
+            let (a, b) = as_fraction(first);
+            let (c, d) = as_fraction(second);
+            let (numerator, denominator) = (a * d + c * b, b * d);
+            let divisor = split::gcd(numerator, denominator);
+            let numerator = u32::try_from(numerator / divisor).ok()?;
+            let denominator = u32::try_from(denominator / divisor).ok()?;
+            Some(Quantity::Fraction(numerator, denominator))
+        }
+        _ => None,
+    }
+}
+
+fn as_fraction(quantity: &Quantity) -> (u128, u128) {
+    match quantity {
+        Quantity::Percent(percent) => (u128::from(*percent), 100),
+        Quantity::Fraction(numerator, denominator) => (u128::from(*numerator), u128::from(*denominator)),
+        _ => unreachable!("only shares can be written as a fraction"),
+    }
+}
+
+/// Parses a pair that is one of the rules in `quantity` from `requests.pest`. Errors
+/// are appended to `errors`, and any error will cause `Err` to be returned.
+fn parse_quantity(
+    pair: ::pest::iterators::Pair<Rule>,
+    errors: &mut Vec<RequestsParseError>,
+) -> Result<Quantity, ()> {
+    let quantity = match pair.as_rule() {
+        Rule::number => Quantity::Count(parse_number(&pair, errors)?),
+        Rule::skip => Quantity::Skip,
+        Rule::rest => Quantity::Rest,
+        Rule::percent => Quantity::Percent(parse_number(&pair.into_inner().next().unwrap(), errors)?),
+        Rule::fraction => {
+            let mut inner = pair.into_inner();
+            let numerator = parse_number(&inner.next().unwrap(), errors);
+            let denominator = parse_number(&inner.next().unwrap(), errors);
+            Quantity::Fraction(numerator?, denominator?)
+        }
+        Rule::pinned => {
+            let mut ranges = vec![];
+            let mut result = Ok(());
+            for range_pair in pair.into_inner() {
+                match parse_range(range_pair, errors) {
+                    Ok(range) => ranges.push(range),
+                    Err(()) => result = Err(()),
+                }
+            }
+            result?;
+            Quantity::Units(ranges)
+        }
+        _ => unreachable!(),
+    };
+    Ok(quantity)
+}
+
+/// Parses a pair that is a `Rule::serial`, `Rule::range` or `Rule::number` into a
+/// Range, or returns an error if the range decreases in value or is larger than
+/// `range::MAX_UNIT`.
+fn parse_range(pair: ::pest::iterators::Pair<Rule>, errors: &mut Vec<RequestsParseError>) -> Result<Range, ()> {
+    match pair.as_rule() {
+        Rule::number => {
+            let num = parse_unit_number(&pair, errors)?;
+            let width = range::padded_width(pair.as_str());
+            Ok(Range::with_prefix(String::new(), num, num, width))
+        }
+        Rule::serial => match pair.as_str().parse() {
+            Ok(range) => Ok(range),
+            Err(RangeParseError::NumberTooLarge) => {
+                errors.push(RequestsParseError::NumberTooLarge {
+                    number: (&pair.as_span()).into(),
+                });
+                Err(())
+            }
+            Err(_) => {
+                errors.push(RequestsParseError::DecreasingRange {
+                    range: (&pair.as_span()).into(),
+                });
+                Err(())
+            }
+        },
+        Rule::range => {
+            let span = pair.as_span();
+            let mut inner = pair.into_inner();
+            let first_pair = inner.next().unwrap();
+            let first = parse_unit_number(&first_pair, errors);
+            let last = parse_unit_number(&inner.next().unwrap(), errors);
+            let (first, last) = (first?, last?);
+            if first > last {
+                errors.push(RequestsParseError::DecreasingRange { range: (&span).into() });
+                return Err(());
+            }
+            let width = range::padded_width(first_pair.as_str());
+            Ok(Range::with_prefix(String::new(), first, last, width))
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Parses a pair that is a `Rule::number`. The grammar only lets digits through, so the
+/// only way this can fail is if the number is too large for `T`.
+fn parse_number<T: FromStr>(
+    pair: &::pest::iterators::Pair<Rule>,
+    errors: &mut Vec<RequestsParseError>,
+) -> Result<T, ()> {
+    pair.as_str().parse().map_err(|_| {
+        errors.push(RequestsParseError::NumberTooLarge {
+            number: (&pair.as_span()).into(),
+        })
+    })
+}
+
+/// Like `parse_number`, but for unit numbers, which can be no larger than
+/// `range::MAX_UNIT`.
+fn parse_unit_number(
+    pair: &::pest::iterators::Pair<Rule>,
+    errors: &mut Vec<RequestsParseError>,
+) -> Result<u64, ()> {
+    range::parse_number(pair.as_str()).map_err(|_| {
+        errors.push(RequestsParseError::NumberTooLarge {
+            number: (&pair.as_span()).into(),
+        })
+    })
 }
 
 #[derive(Fail, Debug, PartialEq)]
 pub enum RequestsParseError {
     #[fail(display = "Invalid syntax: {}", _0)]
     Syntax(#[cause] ::pest::error::Error<Rule>),
+
+    #[fail(display = "Range goes from high to low: {:?}", range)]
+    DecreasingRange { range: OwnedSpan },
+
+    #[fail(display = "Number is too large to parse: {:?}", number)]
+    NumberTooLarge { number: OwnedSpan },
+
+    #[fail(
+        display = "Duplicate request name: request at {:?} has the same name as request at {:?}",
+        duplicate, first
+    )]
+    DuplicateRequest {
+        first: OwnedSpan,
+        duplicate: OwnedSpan,
+    },
+
+    #[fail(display = "Amount is repeated zero times: {:?}", repetition)]
+    ZeroRepetition { repetition: OwnedSpan },
+
+    #[fail(
+        display = "Amounts can't be added together: {:?} is a different kind of amount than {:?}",
+        duplicate, first
+    )]
+    UnmergeableAmounts {
+        first: OwnedSpan,
+        duplicate: OwnedSpan,
+    },
+
+    #[fail(
+        display = "Pinned units {:?} are followed by another request on the same line. Put them in brackets or start the next request on a new line.",
+        units
+    )]
+    UnseparatedPinnedUnits { units: OwnedSpan },
 }
 
 impl From<::pest::error::Error<Rule>> for RequestsParseError {
@@ -64,8 +388,10 @@ impl From<::pest::error::Error<Rule>> for RequestsParseError {
 
 #[cfg(test)]
 mod tests {
-    use crate::request::Request;
-    use crate::requests::{parse, RequestsParser, Rule};
+    use crate::inventory::OwnedSpan;
+    use crate::range::Range;
+    use crate::request::{Amount, Quantity, Request, Source};
+    use crate::requests::{parse, parse_with, ParseOptions, RequestsParseError, RequestsParser, Rule};
 
     #[test]
     fn my_one_request() {
@@ -139,9 +465,449 @@ mod tests {
         assert_eq!(
             result,
             Ok(vec![
-                Request::new("A".to_string(), vec![32, 32, 32]),
-                Request::new("B".to_string(), vec![450, 234, 4]),
+                Request::new("A".to_string(), vec![Amount::new(32); 3]),
+                Request::new(
+                    "B".to_string(),
+                    vec![Amount::new(450), Amount::new(234), Amount::new(4)]
+                ),
             ])
         );
     }
+
+    #[test]
+    fn named_amounts() {
+        parses_to! {
+            parser: RequestsParser,
+            input: "A: CTRL=5, 10",
+            rule: Rule::requests,
+            tokens: [
+                requests(0, 13, [
+                    request(0, 13, [
+                        name(0, 1),
+                        named_amount(3, 9, [
+                            group_name(3, 7),
+                            number(8, 9)
+                        ]),
+                        number(11, 13)
+                    ]),
+                    EOI(13, 13)
+                ])
+            ]
+        };
+    }
+
+    #[test]
+    fn named_amounts_to_data() {
+        let result = parse("A: EVAL=10, CTRL=5\nB: 17, CTRL=2");
+        assert_eq!(
+            result,
+            Ok(vec![
+                Request::new(
+                    "A".to_string(),
+                    vec![
+                        Amount::named("EVAL".into(), 10),
+                        Amount::named("CTRL".into(), 5)
+                    ]
+                ),
+                Request::new(
+                    "B".to_string(),
+                    vec![Amount::new(17), Amount::named("CTRL".into(), 2)]
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn skipped_amounts() {
+        parses_to! {
+            parser: RequestsParser,
+            input: "A: -, 5, CTRL=-",
+            rule: Rule::requests,
+            tokens: [
+                requests(0, 15, [
+                    request(0, 15, [
+                        name(0, 1),
+                        skip(3, 4),
+                        number(6, 7),
+                        named_amount(9, 15, [
+                            group_name(9, 13),
+                            skip(14, 15)
+                        ])
+                    ]),
+                    EOI(15, 15)
+                ])
+            ]
+        };
+    }
+
+    #[test]
+    fn skipped_amounts_to_data() {
+        let result = parse("A: -, 5, CTRL=-");
+        assert_eq!(
+            result,
+            Ok(vec![Request::new(
+                "A".to_string(),
+                vec![
+                    Amount::skip(),
+                    Amount::new(5),
+                    Amount::with_quantity(Some("CTRL".into()), Quantity::Skip),
+                ]
+            )])
+        );
+    }
+
+    #[test]
+    fn shares() {
+        parses_to! {
+            parser: RequestsParser,
+            input: "A: 50%, CTRL=1/4",
+            rule: Rule::requests,
+            tokens: [
+                requests(0, 16, [
+                    request(0, 16, [
+                        name(0, 1),
+                        percent(3, 6, [number(3, 5)]),
+                        named_amount(8, 16, [
+                            group_name(8, 12),
+                            fraction(13, 16, [number(13, 14), denominator(15, 16)])
+                        ])
+                    ]),
+                    EOI(16, 16)
+                ])
+            ]
+        };
+    }
+
+    #[test]
+    fn shares_to_data() {
+        let result = parse("A: 25%x2, 1/3");
+        assert_eq!(
+            result,
+            Ok(vec![Request::new(
+                "A".to_string(),
+                vec![
+                    Amount::with_quantity(None, Quantity::Percent(25)),
+                    Amount::with_quantity(None, Quantity::Percent(25)),
+                    Amount::with_quantity(None, Quantity::Fraction(1, 3)),
+                ]
+            )])
+        );
+    }
+
+    #[test]
+    fn zero_denominator() {
+        assert!(parse("A: 1/0").is_err());
+    }
+
+    #[test]
+    fn rest_to_data() {
+        let result = parse("SPARE: *x2, CTRL=*");
+        assert_eq!(
+            result,
+            Ok(vec![Request::new(
+                "SPARE".to_string(),
+                vec![
+                    Amount::with_quantity(None, Quantity::Rest),
+                    Amount::with_quantity(None, Quantity::Rest),
+                    Amount::with_quantity(Some("CTRL".into()), Quantity::Rest),
+                ]
+            )])
+        );
+    }
+
+    #[test]
+    fn pinned_units() {
+        parses_to! {
+            parser: RequestsParser,
+            input: "A: 10, CTRL=21-25, [3, 5-6]",
+            rule: Rule::requests,
+            tokens: [
+                requests(0, 27, [
+                    request(0, 27, [
+                        name(0, 1),
+                        number(3, 5),
+                        named_amount(7, 17, [
+                            group_name(7, 11),
+                            pinned(12, 17, [range(12, 17, [number(12, 14), number(15, 17)])])
+                        ]),
+                        pinned(19, 27, [
+                            number(20, 21),
+                            range(23, 26, [number(23, 24), number(25, 26)])
+                        ])
+                    ]),
+                    EOI(27, 27)
+                ])
+            ]
+        };
+    }
+
+    #[test]
+    fn pinned_units_to_data() {
+        let result = parse("A: 10, CTRL=21-25, [3, 5-6]");
+        assert_eq!(
+            result,
+            Ok(vec![Request::new(
+                "A".to_string(),
+                vec![
+                    Amount::new(10),
+                    Amount::with_quantity(Some("CTRL".into()), Quantity::Units(vec![Range::new(21, 25)])),
+                    Amount::with_quantity(None, Quantity::Units(vec![Range::num(3), Range::new(5, 6)])),
+                ]
+            )])
+        );
+    }
+
+    #[test]
+    fn pinned_units_before_another_request() {
+        let result = parse("A: 3 LT5 X: 2");
+        assert_eq!(
+            result,
+            Err(vec![RequestsParseError::UnseparatedPinnedUnits {
+                units: OwnedSpan::new(5, 8, "LT5".into()),
+            }])
+        );
+        assert!(parse("A: 3 [LT5] X: 2").is_ok());
+        assert!(parse("A: 3 CTRL=LT5 X: 2").is_ok());
+        assert!(parse("A: 3 LT5\nX: 2").is_ok());
+    }
+
+    #[test]
+    fn decreasing_pinned_range() {
+        let result = parse("A: CTRL=25-21");
+        assert_eq!(
+            result,
+            Err(vec![RequestsParseError::DecreasingRange {
+                range: OwnedSpan::new(8, 13, "25-21".into()),
+            }])
+        );
+    }
+
+    #[test]
+    fn pinned_serial_numbers() {
+        let result = parse("A: LT123-0057-0072, CTRL=[LT9, LT11-LT12], 1/4");
+        assert_eq!(
+            result,
+            Ok(vec![Request::new(
+                "A".to_string(),
+                vec![
+                    Amount::with_quantity(
+                        None,
+                        Quantity::Units(vec![Range::with_prefix("LT123-".into(), 57, 72, 4)])
+                    ),
+                    Amount::with_quantity(
+                        Some("CTRL".into()),
+                        Quantity::Units(vec![
+                            Range::with_prefix("LT".into(), 9, 9, 0),
+                            Range::with_prefix("LT".into(), 11, 12, 0),
+                        ])
+                    ),
+                    Amount::with_quantity(None, Quantity::Fraction(1, 4)),
+                ]
+            )])
+        );
+    }
+
+    #[test]
+    fn duplicate_requests() {
+        let result = parse("A: 1\nB: 2\nA: 3");
+        assert_eq!(
+            result,
+            Err(vec![RequestsParseError::DuplicateRequest {
+                first: OwnedSpan::new(0, 1, "A".into()),
+                duplicate: OwnedSpan::new(10, 11, "A".into()),
+            }])
+        );
+    }
+
+    #[test]
+    fn zero_repetition() {
+        let result = parse("A: 5x0, 3");
+        assert_eq!(
+            result,
+            Err(vec![RequestsParseError::ZeroRepetition {
+                repetition: OwnedSpan::new(3, 6, "5x0".into()),
+            }])
+        );
+    }
+
+    #[test]
+    fn numbers_too_large() {
+        let result = parse("A: 18446744073709551616, 4294967296%, 1x4294967296\nB: [18446744073709551615-1], 1/4294967296");
+        assert_eq!(
+            result,
+            Err(vec![
+                RequestsParseError::NumberTooLarge {
+                    number: OwnedSpan::new(3, 23, "18446744073709551616".into()),
+                },
+                RequestsParseError::NumberTooLarge {
+                    number: OwnedSpan::new(25, 35, "4294967296".into()),
+                },
+                RequestsParseError::NumberTooLarge {
+                    number: OwnedSpan::new(40, 50, "4294967296".into()),
+                },
+                RequestsParseError::NumberTooLarge {
+                    number: OwnedSpan::new(55, 75, "18446744073709551615".into()),
+                },
+                RequestsParseError::NumberTooLarge {
+                    number: OwnedSpan::new(82, 92, "4294967296".into()),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn largest_numbers() {
+        let result = parse("A: 18446744073709551615, [18446744073709551614]");
+        assert_eq!(
+            result,
+            Ok(vec![Request::new(
+                "A".to_string(),
+                vec![
+                    Amount::new(u64::MAX),
+                    Amount::with_quantity(None, Quantity::Units(vec![Range::num(u64::MAX - 1)])),
+                ]
+            )])
+        );
+    }
+
+    #[test]
+    fn merge_duplicates() {
+        let options = ParseOptions { merge_duplicates: true };
+        let result = parse_with("A: 5, 1/4, CTRL=[3]\nB: 1\nA: 2, 25%, 7, CTRL=[9], EVAL=-", &options);
+        assert_eq!(
+            result,
+            Ok(vec![
+                Request::new(
+                    "A".to_string(),
+                    vec![
+                        Amount::new(7),
+                        Amount::with_quantity(None, Quantity::Fraction(1, 2)),
+                        Amount::with_quantity(
+                            Some("CTRL".into()),
+                            Quantity::Units(vec![Range::num(3), Range::num(9)])
+                        ),
+                        Amount::new(7),
+                        Amount::with_quantity(Some("EVAL".into()), Quantity::Skip),
+                    ]
+                ),
+                Request::new("B".to_string(), vec![Amount::new(1)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn unmergeable_amounts() {
+        let options = ParseOptions { merge_duplicates: true };
+        let result = parse_with("A: 5, *\nA: 50%, *", &options);
+        assert_eq!(
+            result,
+            Err(vec![
+                RequestsParseError::UnmergeableAmounts {
+                    first: OwnedSpan::new(3, 4, "5".into()),
+                    duplicate: OwnedSpan::new(11, 14, "50%".into()),
+                },
+                RequestsParseError::UnmergeableAmounts {
+                    first: OwnedSpan::new(6, 7, "*".into()),
+                    duplicate: OwnedSpan::new(16, 17, "*".into()),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let result = parse("# Week 12\n\nA: 10, 5 # for Alice\n# B: 3\nB: EVAL=[2]#retest\n");
+        assert_eq!(
+            result,
+            Ok(vec![
+                Request::new("A".to_string(), vec![Amount::new(10), Amount::new(5)]).with_comment("for Alice".into()),
+                Request::new(
+                    "B".to_string(),
+                    vec![Amount::with_quantity(Some("EVAL".into()), Quantity::Units(vec![Range::num(2)]))]
+                )
+                .with_comment("retest".into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn requests_on_one_line() {
+        // Like the joined arguments of the command line
+        let result = parse("A: 3 B: 2, 1 HTOL 125C: 4 \"E F\": [7] 1");
+        let names: Vec<String> = result.unwrap().iter().map(|r| r.name().to_string()).collect();
+        assert_eq!(names, vec!["A", "B", "HTOL 125C", "E F"]);
+
+        let result = parse("TC: 5 B: 2 from TC C: 1 X: same as HOT TC Y: 3");
+        assert_eq!(
+            result,
+            Ok(vec![
+                Request::new("TC".into(), vec![Amount::new(5)]),
+                Request::new("B".into(), vec![Amount::new(2)]).with_source(Source::From("TC".into())),
+                Request::new("C".into(), vec![Amount::new(1)]),
+                Request::same_as("X".into(), "HOT TC".into()),
+                Request::new("Y".into(), vec![Amount::new(3)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn quoted_names() {
+        let result = parse(r#""A: B": "HTOL 125C"=10, 5"#);
+        assert_eq!(
+            result,
+            Ok(vec![Request::new(
+                "A: B".to_string(),
+                vec![Amount::named("HTOL 125C".into(), 10), Amount::new(5)]
+            )])
+        );
+    }
+
+    #[test]
+    fn reused_units() {
+        parses_to! {
+            parser: RequestsParser,
+            input: "A: same as TC\nB: 40 from TC",
+            rule: Rule::requests,
+            tokens: [
+                requests(0, 27, [
+                    request(0, 14, [name(0, 1), same_as(3, 13, [source(11, 13)])]),
+                    request(14, 27, [name(14, 15), number(17, 19), from(20, 27, [source(25, 27)])]),
+                    EOI(27, 27)
+                ])
+            ]
+        };
+    }
+
+    #[test]
+    fn reused_units_to_data() {
+        let result = parse("TC: 77\nHAST: same as TC\nBURN IN: 40, CTRL=5 from \"T C\"  # after TC");
+        assert_eq!(
+            result,
+            Ok(vec![
+                Request::new("TC".into(), vec![Amount::new(77)]),
+                Request::same_as("HAST".into(), "TC".into()),
+                Request::new("BURN IN".into(), vec![Amount::new(40), Amount::named("CTRL".into(), 5)])
+                    .with_source(Source::From("T C".into()))
+                    .with_comment("after TC".into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn merge_reused_units() {
+        let options = ParseOptions { merge_duplicates: true };
+        assert_eq!(
+            parse_with("A: 5 from TC\nA: 3 from TC", &options),
+            Ok(vec![
+                Request::new("A".into(), vec![Amount::new(8)]).with_source(Source::From("TC".into()))
+            ])
+        );
+        assert_eq!(
+            parse_with("A: 5 from TC\nA: 3", &options),
+            Err(vec![RequestsParseError::DuplicateRequest {
+                first: OwnedSpan::new(0, 1, "A".into()),
+                duplicate: OwnedSpan::new(13, 14, "A".into()),
+            }])
+        );
+    }
 }
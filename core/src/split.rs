@@ -1,70 +1,673 @@
 use crate::group::Group;
-use crate::range::Range;
-use crate::request::Request;
+use crate::interval_tree::IntervalTree;
+use crate::range::{self, Range};
+use crate::range_set::RangeSet;
+use crate::request::{Quantity, Request, Source};
 
-pub type SplitResult = Result<Split, SplitError>;
+use std::fmt;
+use std::str::FromStr;
+
+pub type SplitResult = Result<Split, Vec<SplitError>>;
 
 pub fn split(inventory: &[Group], requests: &[Request]) -> SplitResult {
-    let mut inventory = inventory.to_vec();
-    let mut filled_requests = Vec::new();
-    for request in requests {
-        let mut groups_used_ranges = vec![];
-        for (group_idx, amount) in request.amounts().iter().enumerate() {
-            let group = match inventory.get_mut(group_idx) {
-                Some(group) => group,
-                None => {
-                    // This request is asking for units from a non existant group.
-                    // Is an error, because it would only allow excluding groups
-                    // at the end. May be added back as a feature later.
-                    return Err(SplitError::TooManyGroupsRequested {
+    split_with(inventory, requests, &SplitOptions::default())
+}
+
+/// Splits `inventory` between `requests`. Requests that reuse the units of another
+/// request are filled after it, from its units: requests with `Source::SameAs` get
+/// every unit it was given, and the requests with `Source::From` that reuse the same
+/// request split its units between them. Reused units are still held by the request
+/// they came from, so they don't change the leftover units.
+pub fn split_with(inventory: &[Group], requests: &[Request], options: &SplitOptions) -> SplitResult {
+    if requests.iter().all(|request| request.source().is_none()) {
+        return split_inventory(inventory, requests, options);
+    }
+
+    let mut errors = Vec::new();
+    let sources = resolve_sources(requests, &mut errors);
+    let inventory_requests: Vec<Request> = requests.iter().filter(|r| r.source().is_none()).cloned().collect();
+    let mut split = match split_inventory(inventory, &inventory_requests, options) {
+        Ok(split) if errors.is_empty() => split,
+        Ok(_) => return Err(errors),
+        Err(mut split_errors) => {
+            split_errors.append(&mut errors);
+            return Err(split_errors);
+        }
+    };
+
+    let mut filled: Vec<Option<Vec<Group>>> = vec![None; requests.len()];
+    let mut inventory_filled = std::mem::take(&mut split.filled_requests).into_iter();
+    for (request_idx, request) in requests.iter().enumerate() {
+        if request.source().is_none() {
+            filled[request_idx] = inventory_filled.next().map(|(_, groups)| groups);
+        }
+    }
+
+    // Requests are filled once the request they reuse has been filled. Since there are
+    // no cycles, every request is reached by following its sources back to a request
+    // that was split from the inventory.
+    let mut failed = vec![false; requests.len()];
+    while let Some(source_idx) = (0..requests.len())
+        .find(|&idx| filled[idx].is_some() && (0..requests.len()).any(|r| sources[r] == Some(idx) && filled[r].is_none()))
+    {
+        let reusing: Vec<usize> = (0..requests.len())
+            .filter(|&r| sources[r] == Some(source_idx) && filled[r].is_none())
+            .collect();
+        if failed[source_idx] {
+            for &request_idx in &reusing {
+                filled[request_idx] = Some(vec![]);
+                failed[request_idx] = true;
+            }
+            continue;
+        }
+
+        // The source's units, in a copy of the inventory so that amounts are matched
+        // to groups the same way as for any other request
+        let source_groups = filled[source_idx].clone().unwrap_or_default();
+        let source_inventory: Vec<Group> = inventory
+            .iter()
+            .map(|group| match source_groups.iter().find(|g| g.name() == group.name()) {
+                Some(source_group) => group.with_ranges(source_group.ranges().to_vec()),
+                None => group.with_ranges(vec![]),
+            })
+            .collect();
+        let from_requests: Vec<Request> = reusing
+            .iter()
+            .filter(|&&r| matches!(requests[r].source(), Some(Source::From(_))))
+            .map(|&r| Request::new(requests[r].name().into(), requests[r].amounts().to_vec()))
+            .collect();
+        let from_filled: Vec<Option<Vec<Group>>> = match split_inventory(&source_inventory, &from_requests, options) {
+            Ok(from_split) => {
+                split.shortfalls.extend(from_split.shortfalls);
+                from_split.filled_requests.into_iter().map(|(_, groups)| Some(groups)).collect()
+            }
+            Err(from_errors) => {
+                let source_name = requests[source_idx].name();
+                errors.extend(from_errors.into_iter().map(|e| match e {
+                    SplitError::NotEnough {
+                        request_name,
+                        group_name,
+                        amount_needed,
+                    } => SplitError::NotEnoughInSource {
+                        request_name,
+                        source_name: source_name.into(),
+                        group_name,
+                        amount_needed,
+                    },
+                    e => e,
+                }));
+                vec![None; from_requests.len()]
+            }
+        };
+        let mut from_filled = from_filled.into_iter();
+        for request_idx in reusing {
+            filled[request_idx] = match requests[request_idx].source() {
+                Some(Source::SameAs(_)) => Some(source_groups.clone()),
+                _ => from_filled.next().unwrap(),
+            };
+            if filled[request_idx].is_none() {
+                filled[request_idx] = Some(vec![]);
+                failed[request_idx] = true;
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    split.filled_requests = requests
+        .iter()
+        .map(|r| r.name().to_string())
+        .zip(filled.into_iter().map(Option::unwrap_or_default))
+        .collect();
+    Ok(split)
+}
+
+/// Finds the index of the request that each of `requests` reuses the units of, if
+/// any. An error is appended to `errors` for each source that isn't one of `requests`,
+/// and for each request that ends up reusing its own units.
+fn resolve_sources(requests: &[Request], errors: &mut Vec<SplitError>) -> Vec<Option<usize>> {
+    let sources: Vec<Option<usize>> = requests
+        .iter()
+        .map(|request| {
+            let source_name = request.source()?.request_name();
+            let source_idx = requests.iter().position(|r| r.name() == source_name);
+            if source_idx.is_none() {
+                errors.push(SplitError::UnknownSource {
+                    request_name: request.name().into(),
+                    source_name: source_name.into(),
+                });
+            }
+            source_idx
+        })
+        .collect();
+    for (request_idx, request) in requests.iter().enumerate() {
+        let mut source_idx = sources[request_idx];
+        for _ in 0..requests.len() {
+            match source_idx {
+                Some(idx) if idx == request_idx => {
+                    errors.push(SplitError::CyclicSource {
                         request_name: request.name().into(),
                     });
+                    break;
                 }
-            };
+                Some(idx) => source_idx = sources[idx],
+                None => break,
+            }
+        }
+    }
+    sources
+}
 
-            let unused = {
-                // TODO: NonLexicalLifetimes strikes again!
-                let ranges = group.ranges();
+/// Splits `inventory` between `requests`, none of which reuse the units of another
+/// request
+fn split_inventory(inventory: &[Group], requests: &[Request], options: &SplitOptions) -> SplitResult {
+    let mut errors = Vec::new();
+    let requests_amounts: Vec<_> = requests
+        .iter()
+        .map(|request| resolve_amounts(inventory, request, &mut errors))
+        .collect();
 
-                match split_ranges(ranges, *amount) {
-                    Ok((used, unused)) => {
-                        groups_used_ranges.push(group.with_ranges(used));
-                        unused
-                    }
+    let mut filled_groups = vec![Vec::new(); requests.len()];
+    let mut leftover_ranges = Vec::new();
+    let mut shortfalls = Vec::new();
+    for (group_idx, group) in inventory.iter().enumerate() {
+        let mut request_idxs = Vec::new();
+        let mut quantities = Vec::new();
+        for (request_idx, request_amounts) in requests_amounts.iter().enumerate() {
+            if let Some(ref quantity) = request_amounts[group_idx] {
+                request_idxs.push(request_idx);
+                quantities.push(quantity);
+            }
+        }
+        let available = remove_pinned(group, &request_idxs, &quantities, requests, &mut errors);
+        let (amounts, allocation) = match allocate_group(group, &available, &quantities, options.allocator) {
+            Some(allocated) => allocated,
+            None => {
+                errors.push(SplitError::SharesTooPrecise {
+                    group_name: group.name().into(),
+                });
+                continue;
+            }
+        };
+        for ((request_idx, amount), used) in request_idxs.into_iter().zip(amounts).zip(allocation.used) {
+            let used_count = range::total(&used);
+            if used_count < amount {
+                if !options.partial {
+                    errors.push(SplitError::NotEnough {
+                        request_name: requests[request_idx].name().into(),
+                        group_name: group.name().into(),
+                        amount_needed: amount - used_count,
+                    });
+                    // Keep going so every request that is short will be reported
+                }
+                shortfalls.push(Shortfall {
+                    request_name: requests[request_idx].name().into(),
+                    group_name: group.name().into(),
+                    requested: amount,
+                    missing: amount - used_count,
+                });
+            }
+            filled_groups[request_idx].push(finish_group(group.with_ranges(used), options));
+        }
+        leftover_ranges.push(finish_group(group.with_ranges(allocation.unused), options));
+    }
 
-                    Err(amount_needed) => {
-                        return Err(SplitError::NotEnough {
-                            group_name: group.name().into(),
-                            amount_needed: amount_needed,
-                        });
-                    }
+    let filled_requests = requests
+        .iter()
+        .map(|r| r.name().to_string())
+        .zip(filled_groups)
+        .collect();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Split {
+        filled_requests,
+        leftover_ranges,
+        shortfalls,
+    })
+}
+
+pub struct SplitOptions<'a> {
+    /// Chooses which units of each group go to which request
+    pub allocator: &'a dyn Allocator,
+    /// When `true`, requests that can't be filled are given whatever units are left and
+    /// recorded in `Split::shortfalls`, instead of failing with `SplitError::NotEnough`.
+    pub partial: bool,
+    /// When `true`, the ranges of every filled and leftover group are sorted and merged
+    /// with `Group::normalized`. Set to `false` to keep them in the order they were
+    /// allocated in, which follows the order of the inventory.
+    pub normalize: bool,
+}
+
+impl Default for SplitOptions<'static> {
+    fn default() -> Self {
+        Self {
+            allocator: &FirstFit,
+            partial: false,
+            normalize: true,
+        }
+    }
+}
+
+fn finish_group(group: Group, options: &SplitOptions) -> Group {
+    if options.normalize {
+        group.normalized()
+    } else {
+        group
+    }
+}
+
+/// Matches each amount in `request` to a group in `inventory`. Named amounts go to the
+/// group with that name, and unnamed amounts go to the group at the same position as
+/// the amount has among the other unnamed amounts.
+///
+/// The returned list has an entry for every group in `inventory`, which is `None` if
+/// the request doesn't want anything from that group. Amounts that can't be matched to
+/// a group are left out, and an error is appended to `errors`.
+fn resolve_amounts(
+    inventory: &[Group],
+    request: &Request,
+    errors: &mut Vec<SplitError>,
+) -> Vec<Option<Quantity>> {
+    let mut resolved = vec![None; inventory.len()];
+    let mut requested = vec![false; inventory.len()];
+    let mut position = 0;
+    for amount in request.amounts() {
+        let group_idx = match amount.group() {
+            Some(group_name) => match inventory.iter().position(|g| g.name() == group_name) {
+                Some(idx) => idx,
+                None => {
+                    errors.push(SplitError::UnknownGroup {
+                        request_name: request.name().into(),
+                        group_name: group_name.into(),
+                    });
+                    continue;
                 }
-            };
-            *group = group.with_ranges(unused);
+            },
+            None => {
+                // Asking for more groups than there are is an error. Groups can be
+                // excluded using `Quantity::Skip` instead.
+                if position >= inventory.len() {
+                    errors.push(SplitError::TooManyGroupsRequested {
+                        request_name: request.name().into(),
+                    });
+                    break;
+                }
+                position += 1;
+                position - 1
+            }
+        };
+
+        if requested[group_idx] {
+            errors.push(SplitError::GroupRequestedTwice {
+                request_name: request.name().into(),
+                group_name: inventory[group_idx].name().into(),
+            });
+            continue;
+        }
+        requested[group_idx] = true;
+        resolved[group_idx] = match amount.quantity() {
+            Quantity::Skip => None,
+            quantity => Some(quantity.clone()),
+        };
+    }
+    resolved
+}
+
+/// Checks the units pinned by each of `quantities`, which are for the requests at
+/// `request_idxs`, and returns `group` without the pinned units, which is what is left
+/// for everything else.
+///
+/// Every pinned unit must be in `group`, and can only be pinned by one request.
+/// Otherwise an error is appended to `errors`.
+fn remove_pinned(
+    group: &Group,
+    request_idxs: &[usize],
+    quantities: &[&Quantity],
+    requests: &[Request],
+    errors: &mut Vec<SplitError>,
+) -> Group {
+    let mut pinned_units = RangeSet::new();
+    let mut pinned: IntervalTree<usize> = IntervalTree::new();
+    for (&request_idx, quantity) in request_idxs.iter().zip(quantities) {
+        let ranges = match quantity {
+            Quantity::Units(ranges) => ranges,
+            _ => continue,
+        };
+        for range in ranges {
+            let mut overlaps = vec![];
+            pinned.overlap_search(range, &mut overlaps);
+            for (overlapping_range, first_request_idx) in overlaps {
+                errors.push(SplitError::PinnedTwice {
+                    first_request: requests[first_request_idx].name().into(),
+                    second_request: requests[request_idx].name().into(),
+                    group_name: group.name().into(),
+                    units: range.intersection(&overlapping_range).unwrap(),
+                });
+            }
+            pinned.insert(range.clone(), request_idx);
+
+            let units = RangeSet::from(range.clone());
+            for missing in (&units - group.units()).into_ranges() {
+                errors.push(SplitError::PinnedUnitsUnavailable {
+                    request_name: requests[request_idx].name().into(),
+                    group_name: group.name().into(),
+                    units: missing,
+                });
+            }
+            pinned_units = pinned_units | units;
+        }
+    }
+    group.without(&pinned_units)
+}
+
+/// Allocates the units of `group` to each of `quantities`, returning the number of units
+/// each quantity resolved to along with the allocation.
+///
+/// Pinned units (`Quantity::Units`) are given as is, and must already have been taken
+/// out of `available` by `remove_pinned`. The other quantities are allocated from
+/// `available`, in the order of its ranges.
+///
+/// Quantities of `Quantity::Rest` are allocated after all the others, no matter where
+/// they are in the list, and split whatever is left between them evenly. If it doesn't
+/// divide evenly, the earlier requests get one extra unit each.
+fn allocate_group(
+    group: &Group,
+    available: &Group,
+    quantities: &[&Quantity],
+    allocator: &dyn Allocator,
+) -> Option<(Vec<u64>, Allocation)> {
+    let mut pinned_idxs = Vec::new();
+    let mut rest_idxs = Vec::new();
+    let mut fixed_idxs = Vec::new();
+    for (idx, quantity) in quantities.iter().enumerate() {
+        match quantity {
+            Quantity::Units(_) => pinned_idxs.push(idx),
+            Quantity::Rest => rest_idxs.push(idx),
+            _ => fixed_idxs.push(idx),
+        }
+    }
+
+    let fixed_quantities: Vec<&Quantity> = fixed_idxs.iter().map(|&idx| quantities[idx]).collect();
+    let fixed_amounts = resolve_quantities(group.count(), &fixed_quantities)?;
+    let fixed = allocator.allocate(available.ranges(), &fixed_amounts);
+
+    let left = range::total(&fixed.unused);
+    let rest_amounts: Vec<u64> = (0..rest_idxs.len() as u64)
+        .map(|i| left / rest_idxs.len() as u64 + if i < left % rest_idxs.len() as u64 { 1 } else { 0 })
+        .collect();
+    let rest = allocator.allocate(&fixed.unused, &rest_amounts);
+
+    // Put everything back in the same order as `quantities`
+    let mut amounts = vec![0; quantities.len()];
+    let mut used = vec![Vec::new(); quantities.len()];
+    let fixed_results = fixed_idxs.into_iter().zip(fixed_amounts).zip(fixed.used);
+    let rest_results = rest_idxs.into_iter().zip(rest_amounts).zip(rest.used);
+    for ((idx, amount), ranges) in fixed_results.chain(rest_results) {
+        amounts[idx] = amount;
+        used[idx] = ranges;
+    }
+    for idx in pinned_idxs {
+        if let Quantity::Units(ref ranges) = quantities[idx] {
+            amounts[idx] = RangeSet::from(&ranges[..]).len();
+            used[idx] = ranges.clone();
         }
-        filled_requests.push((request.name().into(), groups_used_ranges));
     }
-    return Ok(Split {
-        filled_requests: filled_requests,
-        leftover_ranges: inventory,
-    });
+    Some((
+        amounts,
+        Allocation {
+            used,
+            unused: rest.unused,
+        },
+    ))
+}
+
+/// Turns the quantities requested from a group of `group_count` units into a number of
+/// units for each request.
+///
+/// Percentages and fractions are first rounded down. The units lost to rounding, which
+/// is the whole part of the sum of what was rounded off, are then handed out one each
+/// to the shares that lost the most, with earlier requests winning ties. This is the
+/// largest remainder method, and means that shares adding up to the whole group will
+/// get every unit in it.
+///
+/// Returns `None` if the remainders can't be added up exactly, which can happen when
+/// there are many fractions with large denominators that have no factors in common.
+fn resolve_quantities(group_count: u64, quantities: &[&Quantity]) -> Option<Vec<u64>> {
+    let mut amounts = Vec::with_capacity(quantities.len());
+    // (index into amounts, numerator, denominator) of each remainder
+    let mut remainders: Vec<(usize, u128, u128)> = Vec::new();
+    for (idx, quantity) in quantities.iter().enumerate() {
+        let (numerator, denominator) = match quantity {
+            Quantity::Count(count) => {
+                amounts.push(*count);
+                continue;
+            }
+            Quantity::Percent(percent) => (u128::from(*percent), 100),
+            Quantity::Fraction(numerator, denominator) => {
+                (u128::from(*numerator), u128::from(*denominator))
+            }
+            Quantity::Skip | Quantity::Rest | Quantity::Units(_) => {
+                unreachable!("skipped groups, the rest of a group and pinned units are handled elsewhere")
+            }
+        };
+        let exact = u128::from(group_count) * numerator;
+        amounts.push((exact / denominator).min(u128::from(u64::MAX)) as u64);
+        remainders.push((idx, exact % denominator, denominator));
+    }
+
+    // Add up the remainders as a fraction to find how many units were rounded off
+    let (mut sum_numerator, mut sum_denominator) = (0u128, 1u128);
+    for &(_, numerator, denominator) in &remainders {
+        sum_numerator = sum_numerator
+            .checked_mul(denominator)?
+            .checked_add(numerator.checked_mul(sum_denominator)?)?;
+        sum_denominator = sum_denominator.checked_mul(denominator)?;
+        let divisor = gcd(sum_numerator, sum_denominator);
+        sum_numerator /= divisor;
+        sum_denominator /= divisor;
+    }
+    let rounded_off = (sum_numerator / sum_denominator) as usize;
+
+    // `sort_by` is stable, so earlier requests stay first when remainders are equal
+    remainders.sort_by(|a, b| (b.1 * a.2).cmp(&(a.1 * b.2)));
+    for &(idx, _, _) in remainders.iter().take(rounded_off) {
+        amounts[idx] = amounts[idx].saturating_add(1);
+    }
+    Some(amounts)
+}
+
+pub(crate) fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Split {
     pub filled_requests: Vec<(String, Vec<Group>)>,
     pub leftover_ranges: Vec<Group>,
+    /// Every request that didn't get all the units it asked for from a group, ordered by
+    /// group, followed by those of requests that reuse another request's units. Only a
+    /// partial split can have shortfalls.
+    pub shortfalls: Vec<Shortfall>,
+}
+
+impl Split {
+    /// The total number of units missing from each group that came up short, in the
+    /// same order as the inventory.
+    pub fn missing_by_group(&self) -> Vec<(String, u64)> {
+        let mut missing: Vec<(String, u64)> = Vec::new();
+        for shortfall in &self.shortfalls {
+            // Shortfalls of requests that reuse units come after the others, so the same
+            // group can come up again further down the list
+            match missing.iter_mut().find(|(group_name, _)| *group_name == shortfall.group_name) {
+                Some((_, total)) => *total = total.saturating_add(shortfall.missing),
+                None => missing.push((shortfall.group_name.clone(), shortfall.missing)),
+            }
+        }
+        missing
+    }
+
+    /// The units that were given out from more than one group, which happens when a
+    /// split uses a group and a group worked out from it, like `ALL` and `GOOD` in
+    /// `GOOD = ALL - FAILED`. Each of these units was given to two requests, or twice to
+    /// the same request. Units that a request reuses from another request are in the
+    /// same group as the units they came from, so they aren't counted.
+    pub fn units_given_twice(&self) -> Vec<GivenTwice> {
+        given_twice(self.filled_requests.iter().flat_map(|(_, groups)| groups))
+    }
+
+    /// Gives units that were given to a request back to the leftover units, for when a
+    /// test is cancelled or doesn't use all of its units. Only the units in `units` are
+    /// given back, or all of the request's units if it is `None`. Returned units are
+    /// merged into the leftover group they came from.
+    ///
+    /// Returns the units that were given back, by group. Nothing is given back if any
+    /// of `units` were not given to the request. Units that another request reuses are
+    /// taken from the request but stay out of the leftover units.
+    pub fn return_units(&mut self, request_name: &str, units: Option<&[Range]>) -> Result<Vec<Group>, Vec<SplitError>> {
+        let request_idx = match self.filled_requests.iter().position(|(name, _)| name == request_name) {
+            Some(idx) => idx,
+            None => {
+                return Err(vec![SplitError::UnknownRequest {
+                    request_name: request_name.into(),
+                }])
+            }
+        };
+        let groups = &self.filled_requests[request_idx].1;
+
+        // The units being returned from each of the request's groups
+        let mut returned: Vec<RangeSet> = groups.iter().map(|group| group.units().clone()).collect();
+        if let Some(units) = units {
+            let mut allocated: IntervalTree<usize> = IntervalTree::new();
+            for (group_idx, group) in groups.iter().enumerate() {
+                for range in group.ranges() {
+                    allocated.insert(range.clone(), group_idx);
+                }
+            }
+
+            returned = vec![RangeSet::new(); groups.len()];
+            let mut errors = vec![];
+            for range in units {
+                let mut overlaps = vec![];
+                allocated.overlap_search(range, &mut overlaps);
+                let mut found = RangeSet::new();
+                for (allocated_range, group_idx) in overlaps {
+                    let common = RangeSet::from(range.intersection(&allocated_range).unwrap());
+                    found = &found | &common;
+                    returned[group_idx] = &returned[group_idx] | &common;
+                }
+                for missing in (&RangeSet::from(range.clone()) - &found).into_ranges() {
+                    errors.push(SplitError::UnitsNotAllocated {
+                        request_name: request_name.into(),
+                        units: missing,
+                    });
+                }
+            }
+            if !errors.is_empty() {
+                return Err(errors);
+            }
+        }
+
+        let mut returned_groups = vec![];
+        for (group_idx, units) in returned.into_iter().enumerate() {
+            if units.is_empty() {
+                continue;
+            }
+            let group = &mut self.filled_requests[request_idx].1[group_idx];
+            *group = group.without(&units);
+            let group = group.clone();
+
+            // Units that are reused by another request are still held by it
+            let held_elsewhere = self
+                .filled_requests
+                .iter()
+                .enumerate()
+                .filter(|&(idx, _)| idx != request_idx)
+                .flat_map(|(_, (_, groups))| groups.iter().filter(|g| g.name() == group.name()))
+                .fold(RangeSet::new(), |held, g| &held | g.units());
+            let freed = &units - &held_elsewhere;
+            if !freed.is_empty() {
+                match self.leftover_ranges.iter_mut().find(|g| g.name() == group.name()) {
+                    Some(leftover) => *leftover = leftover.with_units(leftover.units() | &freed),
+                    None => self.leftover_ranges.push(Group::new(group.name().into(), freed.into_ranges())),
+                }
+            }
+            returned_groups.push(group.with_units(units));
+        }
+        Ok(returned_groups)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Shortfall {
+    pub request_name: String,
+    pub group_name: String,
+    /// The number of units the request asked for from the group
+    pub requested: u64,
+    /// How many of the requested units could not be given to the request
+    pub missing: u64,
+}
+
+/// Units that were given out from two different groups
+#[derive(Debug, PartialEq)]
+pub struct GivenTwice {
+    pub first_group: String,
+    pub second_group: String,
+    pub units: RangeSet,
+}
+
+impl fmt::Display for GivenTwice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Units {} were given out from both {} and {}",
+            self.units, self.first_group, self.second_group
+        )
+    }
+}
+
+/// Finds the units that are in groups with different names among `groups`, in the
+/// order the groups first come up
+pub(crate) fn given_twice<'a>(groups: impl Iterator<Item = &'a Group>) -> Vec<GivenTwice> {
+    let mut given: Vec<(&str, RangeSet)> = Vec::new();
+    for group in groups {
+        match given.iter_mut().find(|(name, _)| *name == group.name()) {
+            Some((_, units)) => *units = &*units | group.units(),
+            None => given.push((group.name(), group.units().clone())),
+        }
+    }
+    let mut twice = Vec::new();
+    for (idx, (first_group, first_units)) in given.iter().enumerate() {
+        for (second_group, second_units) in &given[idx + 1..] {
+            let units = first_units & second_units;
+            if !units.is_empty() {
+                twice.push(GivenTwice {
+                    first_group: first_group.to_string(),
+                    second_group: second_group.to_string(),
+                    units,
+                });
+            }
+        }
+    }
+    twice
 }
 
 #[derive(Fail, Debug, PartialEq)]
 pub enum SplitError {
     #[fail(
-        display = "There are not enough units in group {}. {} more needed",
-        group_name, amount_needed
+        display = "There are not enough units in group {} for request \"{}\". {} more needed",
+        group_name, request_name, amount_needed
     )]
     NotEnough {
+        request_name: String,
         group_name: String,
-        amount_needed: u32,
+        amount_needed: u64,
     },
 
     #[fail(
@@ -72,13 +675,335 @@ pub enum SplitError {
         request_name
     )]
     TooManyGroupsRequested { request_name: String },
+
+    #[fail(
+        display = "The request \"{}\" is asking for units from group \"{}\", which is not in the inventory.",
+        request_name, group_name
+    )]
+    UnknownGroup {
+        request_name: String,
+        group_name: String,
+    },
+
+    #[fail(
+        display = "The request \"{}\" is asking for units from group \"{}\" more than once.",
+        request_name, group_name
+    )]
+    GroupRequestedTwice {
+        request_name: String,
+        group_name: String,
+    },
+
+    #[fail(
+        display = "The request \"{}\" pins units {} from group {}, but they are not in the inventory.",
+        request_name, units, group_name
+    )]
+    PinnedUnitsUnavailable {
+        request_name: String,
+        group_name: String,
+        units: Range,
+    },
+
+    #[fail(
+        display = "Units {} from group {} are pinned by both \"{}\" and \"{}\".",
+        units, group_name, first_request, second_request
+    )]
+    PinnedTwice {
+        first_request: String,
+        second_request: String,
+        group_name: String,
+        units: Range,
+    },
+
+    #[fail(
+        display = "The shares requested from group {} can't be added up exactly. Use fractions with smaller denominators.",
+        group_name
+    )]
+    SharesTooPrecise { group_name: String },
+
+    #[fail(display = "There is no request named \"{}\".", request_name)]
+    UnknownRequest { request_name: String },
+
+    #[fail(
+        display = "The request \"{}\" reuses the units of \"{}\", which is not a request.",
+        request_name, source_name
+    )]
+    UnknownSource {
+        request_name: String,
+        source_name: String,
+    },
+
+    #[fail(
+        display = "The request \"{}\" ends up reusing its own units.",
+        request_name
+    )]
+    CyclicSource { request_name: String },
+
+    #[fail(
+        display = "The request \"{}\" doesn't have enough units in group {} for request \"{}\". {} more needed",
+        source_name, group_name, request_name, amount_needed
+    )]
+    NotEnoughInSource {
+        request_name: String,
+        source_name: String,
+        group_name: String,
+        amount_needed: u64,
+    },
+
+    #[fail(
+        display = "Units {} were not given to the request \"{}\", so they can't be returned.",
+        units, request_name
+    )]
+    UnitsNotAllocated { request_name: String, units: Range },
+
+    #[fail(
+        display = "The request \"{}\" doesn't hold any units, so there is nothing to return.",
+        request_name
+    )]
+    NothingHeld { request_name: String },
+}
+
+/// Chooses which units of a group are given to each request.
+pub trait Allocator {
+    /// Takes `amounts[i]` units out of `ranges` for each `i`, in whatever order the
+    /// allocator prefers. If there are not enough units, an amount may be given fewer
+    /// units than it asked for; it is up to the caller to check.
+    fn allocate(&self, ranges: &[Range], amounts: &[u64]) -> Allocation;
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Allocation {
+    /// The units given to each amount, in the same order as the amounts
+    pub used: Vec<Vec<Range>>,
+    /// The units that were not given to any amount
+    pub unused: Vec<Range>,
+}
+
+/// Gives each request the first units available, in the order the ranges are listed.
+/// This is the default allocator.
+pub struct FirstFit;
+
+impl Allocator for FirstFit {
+    fn allocate(&self, ranges: &[Range], amounts: &[u64]) -> Allocation {
+        let mut unused = ranges.to_vec();
+        let mut used = Vec::new();
+        for amount in amounts {
+            let (taken, rest) = take_from_front(&unused, *amount);
+            used.push(taken);
+            unused = rest;
+        }
+        Allocation { used, unused }
+    }
+}
+
+/// Gives each request units from the smallest single range that can hold all of
+/// them, so that requests aren't split across ranges if it can be helped. Falls back
+/// to `FirstFit` if no range is large enough.
+pub struct BestFit;
+
+impl Allocator for BestFit {
+    fn allocate(&self, ranges: &[Range], amounts: &[u64]) -> Allocation {
+        let mut unused = ranges.to_vec();
+        let mut used = Vec::new();
+        for amount in amounts {
+            let best = unused
+                .iter()
+                .enumerate()
+                .filter(|(_, r)| r.count() >= *amount)
+                .min_by_key(|(_, r)| r.count())
+                .map(|(idx, _)| idx);
+            match best {
+                Some(idx) if *amount > 0 => {
+                    let (taken, rest, _) = unused[idx].split(*amount);
+                    used.push(taken.into_iter().collect());
+                    match rest {
+                        Some(rest) => unused[idx] = rest,
+                        None => {
+                            unused.remove(idx);
+                        }
+                    }
+                }
+                _ => {
+                    let (taken, rest) = take_from_front(&unused, *amount);
+                    used.push(taken);
+                    unused = rest;
+                }
+            }
+        }
+        Allocation { used, unused }
+    }
+}
+
+/// Gives each request the last units available, working backwards from the end of
+/// the last range.
+pub struct TakeFromEnd;
+
+impl Allocator for TakeFromEnd {
+    fn allocate(&self, ranges: &[Range], amounts: &[u64]) -> Allocation {
+        let mut unused = ranges.to_vec();
+        let mut used = Vec::new();
+        for amount in amounts {
+            let mut amount = *amount;
+            let mut taken = Vec::new();
+            while amount > 0 {
+                let range = match unused.pop() {
+                    Some(r) => r,
+                    None => break,
+                };
+                if range.count() > amount {
+                    let (rest, end, _) = range.split(range.count() - amount);
+                    unused.extend(rest);
+                    taken.extend(end);
+                    amount = 0;
+                } else {
+                    amount -= range.count();
+                    taken.push(range);
+                }
+            }
+            taken.reverse();
+            used.push(taken);
+        }
+        Allocation { used, unused }
+    }
+}
+
+/// Deals units out one at a time to each request in turn, like a deck of cards, so
+/// that every request gets units from across the whole group. Whole rounds are dealt
+/// at once: when `n` requests are waiting, each one gets every `n`th unit of a run of
+/// units, like `1-99/2` and `2-100/2`.
+pub struct RoundRobin;
+
+impl Allocator for RoundRobin {
+    fn allocate(&self, ranges: &[Range], amounts: &[u64]) -> Allocation {
+        let mut used = vec![Vec::<Range>::new(); amounts.len()];
+        let mut remaining = amounts.to_vec();
+        let mut ranges = ranges.iter().cloned();
+        let mut current = ranges.next();
+        // The request that gets the next unit, if it still needs any
+        let mut turn = 0;
+        while let Some(range) = current.take() {
+            let waiting: Vec<usize> = (turn..amounts.len())
+                .chain(0..turn)
+                .filter(|&i| remaining[i] > 0)
+                .collect();
+            let fewest = match waiting.iter().map(|&i| remaining[i]).min() {
+                Some(fewest) => fewest,
+                None => {
+                    current = Some(range);
+                    break;
+                }
+            };
+            let n = waiting.len() as u64;
+            let rounds = fewest.min(range.count() / n);
+            let rest = if rounds > 0 {
+                let (taken, rest, _) = range.split(rounds * n);
+                let taken = taken.expect("at least one unit was taken");
+                for (offset, &idx) in waiting.iter().enumerate() {
+                    let first = taken.first() + offset as u64 * taken.step();
+                    let last = first + (rounds - 1) * n * taken.step();
+                    let units = Range::with_prefix(taken.prefix().into(), first, last, taken.width())
+                        .with_step(n * taken.step());
+                    push_contiguous(&mut used[idx], units);
+                    remaining[idx] -= rounds;
+                }
+                rest
+            } else {
+                // Too few units left in this range for a whole round
+                let idx = waiting[0];
+                let (taken, rest, _) = range.split(1);
+                push_contiguous(&mut used[idx], taken.expect("the range has a unit"));
+                remaining[idx] -= 1;
+                turn = (idx + 1) % amounts.len();
+                rest
+            };
+            current = rest.or_else(|| ranges.next());
+        }
+        let unused = current.into_iter().chain(ranges).collect();
+        Allocation { used, unused }
+    }
+}
+
+/// Adds `range` to the end of `ranges`, extending the last range instead if the two
+/// are contiguous.
+fn push_contiguous(ranges: &mut Vec<Range>, range: Range) {
+    if let Some(last) = ranges.last_mut() {
+        if let Some(merged) = last.merge(&range) {
+            *last = merged;
+            return;
+        }
+    }
+    ranges.push(range);
+}
+
+/// The allocators that come with the unit splitter, so that they can be chosen by name.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Strategy {
+    #[default]
+    FirstFit,
+    BestFit,
+    TakeFromEnd,
+    RoundRobin,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 4] = [
+        Strategy::FirstFit,
+        Strategy::BestFit,
+        Strategy::TakeFromEnd,
+        Strategy::RoundRobin,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::FirstFit => "first-fit",
+            Strategy::BestFit => "best-fit",
+            Strategy::TakeFromEnd => "from-end",
+            Strategy::RoundRobin => "round-robin",
+        }
+    }
+
+    pub fn allocator(self) -> &'static dyn Allocator {
+        match self {
+            Strategy::FirstFit => &FirstFit,
+            Strategy::BestFit => &BestFit,
+            Strategy::TakeFromEnd => &TakeFromEnd,
+            Strategy::RoundRobin => &RoundRobin,
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = UnknownStrategy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Strategy::ALL
+            .iter()
+            .cloned()
+            .find(|strategy| strategy.name() == s)
+            .ok_or_else(|| UnknownStrategy(s.into()))
+    }
 }
 
-fn split_ranges(ranges: &[Range], mut amount: u32) -> Result<(Vec<Range>, Vec<Range>), u32> {
+#[derive(Fail, Debug, PartialEq)]
+#[fail(
+    display = "Unknown strategy \"{}\", expected one of first-fit, best-fit, from-end or round-robin",
+    _0
+)]
+pub struct UnknownStrategy(String);
+
+/// Takes up to `amount` units from the start of `ranges`. Returns the units that were
+/// taken, and the units that are left.
+fn take_from_front(ranges: &[Range], mut amount: u64) -> (Vec<Range>, Vec<Range>) {
     let mut ranges_iter = ranges.iter();
     let mut used_ranges = Vec::new();
     let mut unused_ranges = Vec::new();
-    loop {
+    while amount > 0 {
         let range = match ranges_iter.next() {
             Some(r) => r,
             None => break,
@@ -91,23 +1016,18 @@ fn split_ranges(ranges: &[Range], mut amount: u32) -> Result<(Vec<Range>, Vec<Ra
         amount = amount_left;
         if let Some(range) = unused {
             unused_ranges.push(range);
-            break;
         }
     }
-    if amount > 0 {
-        Err(amount)
-    } else {
-        unused_ranges.extend(ranges_iter.map(|x| x.clone()));
-        Ok((used_ranges, unused_ranges))
-    }
+    unused_ranges.extend(ranges_iter.cloned());
+    (used_ranges, unused_ranges)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::group::Group;
     use crate::range::Range;
-    use crate::request::Request;
-    use crate::split::{split, Split, SplitError};
+    use crate::request::{Amount, Quantity, Request, Source};
+    use crate::split::*;
 
     #[test]
     fn simple() {
@@ -117,9 +1037,9 @@ mod tests {
             Group::new("C".into(), vec![Range::new(201, 300)]),
         ];
         let requests = vec![
-            Request::new("X".into(), vec![32, 32, 32]),
-            Request::new("Y".into(), vec![32, 32, 32]),
-            Request::new("Z".into(), vec![32, 32, 32]),
+            Request::new("X".into(), vec![Amount::new(32); 3]),
+            Request::new("Y".into(), vec![Amount::new(32); 3]),
+            Request::new("Z".into(), vec![Amount::new(32); 3]),
         ];
 
         let result = split(&inventory, &requests);
@@ -159,6 +1079,7 @@ mod tests {
                     Group::new("B".into(), vec![Range::new(197, 200)]),
                     Group::new("C".into(), vec![Range::new(297, 300)]),
                 ],
+                shortfalls: vec![],
             })
         );
     }
@@ -169,7 +1090,7 @@ mod tests {
         // H: 5
         // J: 6
         let inventory = vec![Group::new("A".into(), vec![Range::new(1, 10), Range::num(15), Range::num(18)])];
-        let requests = vec![Request::new("H".into(), vec![5]), Request::new("J".into(), vec![6])];
+        let requests = vec![Request::new("H".into(), vec![Amount::new(5)]), Request::new("J".into(), vec![Amount::new(6)])];
 
         let result = split(&inventory, &requests);
 
@@ -194,6 +1115,7 @@ mod tests {
                 leftover_ranges: vec![
                     Group::new("A".into(), vec![Range::num(18)]),
                 ],
+                shortfalls: vec![],
             })
         );
     }
@@ -201,32 +1123,965 @@ mod tests {
     #[test]
     fn not_enough() {
         let inventory = vec![Group::new("A".into(), vec![Range::new(1, 10)])];
-        let requests = vec![Request::new("X".into(), vec![32])];
+        let requests = vec![Request::new("X".into(), vec![Amount::new(32)])];
 
         let result = split(&inventory, &requests);
 
         assert_eq!(
             result,
-            Err(SplitError::NotEnough {
+            Err(vec![SplitError::NotEnough {
+                request_name: "X".into(),
                 group_name: "A".into(),
                 amount_needed: 22,
-            })
+            }])
         );
     }
 
     #[test]
     fn greedy_request() {
         let inventory = vec![Group::new("A".into(), vec![Range::new(1, 10)])];
-        let requests = vec![Request::new("X".into(), vec![10, 10])];
+        let requests = vec![Request::new("X".into(), vec![Amount::new(10); 2])];
 
         let result = split(&inventory, &requests);
 
         assert_eq!(
             result,
-            Err(SplitError::TooManyGroupsRequested {
+            Err(vec![SplitError::TooManyGroupsRequested {
                 request_name: "X".into(),
+            }])
+        );
+    }
+
+    #[test]
+    fn named_amounts() {
+        let inventory = vec![
+            Group::new("EVAL".into(), vec![Range::new(1, 50)]),
+            Group::new("CTRL".into(), vec![Range::new(51, 100)]),
+        ];
+        let requests = vec![
+            Request::new("A".into(), vec![Amount::named("CTRL".into(), 5), Amount::new(10)]),
+            Request::new("B".into(), vec![Amount::named("CTRL".into(), 2)]),
+        ];
+
+        let result = split(&inventory, &requests);
+
+        assert_eq!(
+            result,
+            Ok(Split {
+                filled_requests: vec![
+                    (
+                        "A".into(),
+                        vec![
+                            Group::new("EVAL".into(), vec![Range::new(1, 10)]),
+                            Group::new("CTRL".into(), vec![Range::new(51, 55)]),
+                        ]
+                    ),
+                    (
+                        "B".into(),
+                        vec![Group::new("CTRL".into(), vec![Range::new(56, 57)])]
+                    ),
+                ],
+                leftover_ranges: vec![
+                    Group::new("EVAL".into(), vec![Range::new(11, 50)]),
+                    Group::new("CTRL".into(), vec![Range::new(58, 100)]),
+                ],
+                shortfalls: vec![],
             })
         );
     }
 
+    #[test]
+    fn unknown_group() {
+        let inventory = vec![Group::new("EVAL".into(), vec![Range::new(1, 10)])];
+        let requests = vec![Request::new("X".into(), vec![Amount::named("CTRL".into(), 5)])];
+
+        let result = split(&inventory, &requests);
+
+        assert_eq!(
+            result,
+            Err(vec![SplitError::UnknownGroup {
+                request_name: "X".into(),
+                group_name: "CTRL".into(),
+            }])
+        );
+    }
+
+    #[test]
+    fn group_requested_twice() {
+        let inventory = vec![Group::new("EVAL".into(), vec![Range::new(1, 10)])];
+        let requests = vec![Request::new(
+            "X".into(),
+            vec![Amount::new(2), Amount::named("EVAL".into(), 5)],
+        )];
+
+        let result = split(&inventory, &requests);
+
+        assert_eq!(
+            result,
+            Err(vec![SplitError::GroupRequestedTwice {
+                request_name: "X".into(),
+                group_name: "EVAL".into(),
+            }])
+        );
+    }
+
+    #[test]
+    fn skipped_groups() {
+        let inventory = vec![
+            Group::new("EVAL".into(), vec![Range::new(1, 50)]),
+            Group::new("CTRL".into(), vec![Range::new(51, 100)]),
+            Group::new("SPARE".into(), vec![Range::new(101, 110)]),
+        ];
+        let requests = vec![
+            Request::new("A".into(), vec![Amount::skip(), Amount::new(5), Amount::new(0)]),
+            Request::new(
+                "B".into(),
+                vec![
+                    Amount::with_quantity(Some("CTRL".into()), Quantity::Skip),
+                    Amount::new(2),
+                ],
+            ),
+        ];
+
+        let result = split(&inventory, &requests);
+
+        assert_eq!(
+            result,
+            Ok(Split {
+                filled_requests: vec![
+                    (
+                        "A".into(),
+                        vec![
+                            Group::new("CTRL".into(), vec![Range::new(51, 55)]),
+                            Group::new("SPARE".into(), vec![]),
+                        ]
+                    ),
+                    (
+                        "B".into(),
+                        vec![Group::new("EVAL".into(), vec![Range::new(1, 2)])]
+                    ),
+                ],
+                leftover_ranges: vec![
+                    Group::new("EVAL".into(), vec![Range::new(3, 50)]),
+                    Group::new("CTRL".into(), vec![Range::new(56, 100)]),
+                    Group::new("SPARE".into(), vec![Range::new(101, 110)]),
+                ],
+                shortfalls: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn best_fit() {
+        let ranges = vec![Range::new(1, 3), Range::new(10, 20), Range::new(30, 34)];
+
+        let allocation = BestFit.allocate(&ranges, &[5, 4, 2]);
+
+        assert_eq!(
+            allocation,
+            Allocation {
+                used: vec![
+                    vec![Range::new(30, 34)],
+                    vec![Range::new(10, 13)],
+                    vec![Range::new(1, 2)],
+                ],
+                unused: vec![Range::num(3), Range::new(14, 20)],
+            }
+        );
+    }
+
+    #[test]
+    fn best_fit_falls_back_to_first_fit() {
+        let ranges = vec![Range::new(1, 3), Range::new(10, 12)];
+
+        let allocation = BestFit.allocate(&ranges, &[4]);
+
+        assert_eq!(
+            allocation,
+            Allocation {
+                used: vec![vec![Range::new(1, 3), Range::num(10)]],
+                unused: vec![Range::new(11, 12)],
+            }
+        );
+    }
+
+    #[test]
+    fn take_from_end() {
+        let ranges = vec![Range::new(1, 10), Range::new(20, 22)];
+
+        let allocation = TakeFromEnd.allocate(&ranges, &[2, 5]);
+
+        assert_eq!(
+            allocation,
+            Allocation {
+                used: vec![
+                    vec![Range::new(21, 22)],
+                    vec![Range::new(7, 10), Range::num(20)],
+                ],
+                unused: vec![Range::new(1, 6)],
+            }
+        );
+    }
+
+    #[test]
+    fn round_robin() {
+        let ranges = vec![Range::new(1, 10)];
+
+        let allocation = RoundRobin.allocate(&ranges, &[2, 4]);
+
+        assert_eq!(
+            allocation,
+            Allocation {
+                used: vec![
+                    vec![Range::new(1, 3).with_step(2)],
+                    vec![Range::new(2, 4).with_step(2), Range::new(5, 6)],
+                ],
+                unused: vec![Range::new(7, 10)],
+            }
+        );
+
+        // A round can carry on into the next range
+        let ranges = vec![Range::new(1, 5), Range::new(11, 13)];
+        let allocation = RoundRobin.allocate(&ranges, &[4, 4]);
+        assert_eq!(
+            allocation.used,
+            vec![
+                vec![Range::new(1, 5).with_step(2), Range::num(12)],
+                vec![Range::new(2, 4).with_step(2), Range::num(11), Range::num(13)],
+            ]
+        );
+    }
+
+    #[test]
+    fn round_robin_whole_rounds() {
+        let ranges = vec![Range::new(1, 4_000_000)];
+        let allocation = RoundRobin.allocate(&ranges, &[2_000_000, 2_000_000]);
+        assert_eq!(
+            allocation.used,
+            vec![
+                vec![Range::new(1, 3_999_999).with_step(2)],
+                vec![Range::new(2, 4_000_000).with_step(2)],
+            ]
+        );
+
+        // The work depends on the number of ranges, not the number of units
+        let ranges = vec![Range::new(1, 1_000_000_000_000)];
+        let allocation = RoundRobin.allocate(&ranges, &[500_000_000_000, 500_000_000_001]);
+        assert_eq!(allocation.used[0], vec![Range::new(1, 999_999_999_999).with_step(2)]);
+        assert_eq!(allocation.used[1], vec![Range::new(2, 1_000_000_000_000).with_step(2)]);
+        assert!(allocation.unused.is_empty());
+    }
+
+    #[test]
+    fn split_with_strategy() {
+        let inventory = vec![Group::new("A".into(), vec![Range::new(1, 10)])];
+        let requests = vec![Request::new("X".into(), vec![Amount::new(3)])];
+        let strategy: Strategy = "from-end".parse().unwrap();
+
+        let options = SplitOptions {
+            allocator: strategy.allocator(),
+            ..SplitOptions::default()
+        };
+
+        let result = split_with(&inventory, &requests, &options);
+
+        assert_eq!(
+            result,
+            Ok(Split {
+                filled_requests: vec![(
+                    "X".into(),
+                    vec![Group::new("A".into(), vec![Range::new(8, 10)])]
+                )],
+                leftover_ranges: vec![Group::new("A".into(), vec![Range::new(1, 7)])],
+                shortfalls: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn partial_split() {
+        let inventory = vec![
+            Group::new("EVAL".into(), vec![Range::new(1, 10)]),
+            Group::new("CTRL".into(), vec![Range::new(11, 15)]),
+        ];
+        let requests = vec![
+            Request::new("X".into(), vec![Amount::new(8), Amount::new(4)]),
+            Request::new("Y".into(), vec![Amount::new(5), Amount::new(4)]),
+        ];
+        let options = SplitOptions {
+            partial: true,
+            ..SplitOptions::default()
+        };
+
+        let result = split_with(&inventory, &requests, &options).unwrap();
+
+        assert_eq!(
+            result,
+            Split {
+                filled_requests: vec![
+                    (
+                        "X".into(),
+                        vec![
+                            Group::new("EVAL".into(), vec![Range::new(1, 8)]),
+                            Group::new("CTRL".into(), vec![Range::new(11, 14)]),
+                        ]
+                    ),
+                    (
+                        "Y".into(),
+                        vec![
+                            Group::new("EVAL".into(), vec![Range::new(9, 10)]),
+                            Group::new("CTRL".into(), vec![Range::num(15)]),
+                        ]
+                    ),
+                ],
+                leftover_ranges: vec![
+                    Group::new("EVAL".into(), vec![]),
+                    Group::new("CTRL".into(), vec![]),
+                ],
+                shortfalls: vec![
+                    Shortfall {
+                        request_name: "Y".into(),
+                        group_name: "EVAL".into(),
+                        requested: 5,
+                        missing: 3,
+                    },
+                    Shortfall {
+                        request_name: "Y".into(),
+                        group_name: "CTRL".into(),
+                        requested: 4,
+                        missing: 3,
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            result.missing_by_group(),
+            vec![("EVAL".to_string(), 3), ("CTRL".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn missing_by_group_with_reused_units() {
+        let inventory = vec![
+            Group::new("EVAL".into(), vec![Range::new(1, 10)]),
+            Group::new("CTRL".into(), vec![Range::new(11, 20)]),
+        ];
+        let requests = vec![
+            Request::new("X".into(), vec![Amount::new(8), Amount::new(8)]),
+            Request::new("Y".into(), vec![Amount::new(5), Amount::new(5)]),
+            Request::new("Z".into(), vec![Amount::new(10)]).with_source(Source::From("X".into())),
+        ];
+        let options = SplitOptions {
+            partial: true,
+            ..SplitOptions::default()
+        };
+
+        let result = split_with(&inventory, &requests, &options).unwrap();
+        let shortfalls: Vec<(&str, &str, u64)> = result
+            .shortfalls
+            .iter()
+            .map(|s| (s.request_name.as_str(), s.group_name.as_str(), s.missing))
+            .collect();
+        assert_eq!(shortfalls, vec![("Y", "EVAL", 3), ("Y", "CTRL", 3), ("Z", "EVAL", 2)]);
+        assert_eq!(
+            result.missing_by_group(),
+            vec![("EVAL".to_string(), 5), ("CTRL".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn all_errors() {
+        let inventory = vec![
+            Group::new("EVAL".into(), vec![Range::new(1, 10)]),
+            Group::new("CTRL".into(), vec![Range::new(11, 15)]),
+        ];
+        let requests = vec![
+            Request::new("X".into(), vec![Amount::new(8), Amount::new(6)]),
+            Request::new("Y".into(), vec![Amount::new(5), Amount::new(1), Amount::new(1)]),
+            Request::new("Z".into(), vec![Amount::named("SPARE".into(), 1)]),
+        ];
+
+        let result = split(&inventory, &requests);
+
+        assert_eq!(
+            result,
+            Err(vec![
+                SplitError::TooManyGroupsRequested {
+                    request_name: "Y".into(),
+                },
+                SplitError::UnknownGroup {
+                    request_name: "Z".into(),
+                    group_name: "SPARE".into(),
+                },
+                SplitError::NotEnough {
+                    request_name: "Y".into(),
+                    group_name: "EVAL".into(),
+                    amount_needed: 3,
+                },
+                SplitError::NotEnough {
+                    request_name: "X".into(),
+                    group_name: "CTRL".into(),
+                    amount_needed: 1,
+                },
+                SplitError::NotEnough {
+                    request_name: "Y".into(),
+                    group_name: "CTRL".into(),
+                    amount_needed: 1,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn shares_use_largest_remainder() {
+        let third = Quantity::Fraction(1, 3);
+        assert_eq!(resolve_quantities(10, &[&third, &third, &third]), Some(vec![4, 3, 3]));
+
+        let half = Quantity::Percent(50);
+        let quarter = Quantity::Fraction(1, 4);
+        let count = Quantity::Count(1);
+        assert_eq!(resolve_quantities(7, &[&half, &quarter, &count]), Some(vec![3, 2, 1]));
+    }
+
+    #[test]
+    fn shares_too_precise() {
+        // Primes just under 2^32, so the sum of the remainders needs more than 128 bits
+        let inventory = vec![Group::new("EVAL".into(), vec![Range::new(1, 1000)])];
+        let requests: Vec<Request> = [4294967291, 4294967279, 4294967231, 4294967197, 4294967189]
+            .iter()
+            .enumerate()
+            .map(|(idx, &denominator)| {
+                Request::new(
+                    idx.to_string(),
+                    vec![Amount::with_quantity(None, Quantity::Fraction(1, denominator))],
+                )
+            })
+            .collect();
+        assert_eq!(
+            split(&inventory, &requests),
+            Err(vec![SplitError::SharesTooPrecise {
+                group_name: "EVAL".into()
+            }])
+        );
+    }
+
+    #[test]
+    fn split_shares() {
+        let inventory = vec![Group::new("EVAL".into(), vec![Range::new(1, 10)])];
+        let requests = vec![
+            Request::new("A".into(), vec![Amount::with_quantity(None, Quantity::Percent(50))]),
+            Request::new("B".into(), vec![Amount::with_quantity(None, Quantity::Fraction(1, 4))]),
+        ];
+
+        let result = split(&inventory, &requests);
+
+        assert_eq!(
+            result,
+            Ok(Split {
+                filled_requests: vec![
+                    (
+                        "A".into(),
+                        vec![Group::new("EVAL".into(), vec![Range::new(1, 5)])]
+                    ),
+                    (
+                        "B".into(),
+                        vec![Group::new("EVAL".into(), vec![Range::new(6, 7)])]
+                    ),
+                ],
+                leftover_ranges: vec![Group::new("EVAL".into(), vec![Range::new(8, 10)])],
+                shortfalls: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn rest_of_group() {
+        let inventory = vec![
+            Group::new("EVAL".into(), vec![Range::new(1, 10)]),
+            Group::new("CTRL".into(), vec![Range::new(11, 15)]),
+        ];
+        let requests = vec![
+            Request::new(
+                "SPARE".into(),
+                vec![Amount::with_quantity(None, Quantity::Rest), Amount::with_quantity(None, Quantity::Rest)],
+            ),
+            Request::new("A".into(), vec![Amount::new(3), Amount::new(1)]),
+            Request::new(
+                "RETEST".into(),
+                vec![Amount::skip(), Amount::with_quantity(None, Quantity::Rest)],
+            ),
+        ];
+
+        let result = split(&inventory, &requests);
+
+        assert_eq!(
+            result,
+            Ok(Split {
+                filled_requests: vec![
+                    (
+                        "SPARE".into(),
+                        vec![
+                            Group::new("EVAL".into(), vec![Range::new(4, 10)]),
+                            Group::new("CTRL".into(), vec![Range::new(12, 13)]),
+                        ]
+                    ),
+                    (
+                        "A".into(),
+                        vec![
+                            Group::new("EVAL".into(), vec![Range::new(1, 3)]),
+                            Group::new("CTRL".into(), vec![Range::num(11)]),
+                        ]
+                    ),
+                    (
+                        "RETEST".into(),
+                        vec![Group::new("CTRL".into(), vec![Range::new(14, 15)])]
+                    ),
+                ],
+                leftover_ranges: vec![
+                    Group::new("EVAL".into(), vec![]),
+                    Group::new("CTRL".into(), vec![]),
+                ],
+                shortfalls: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn pinned_units() {
+        let inventory = vec![
+            Group::new("EVAL".into(), vec![Range::new(1, 10)]),
+            Group::new("CTRL".into(), vec![Range::new(21, 30)]),
+        ];
+        let requests = vec![
+            Request::new("A".into(), vec![Amount::new(3), Amount::new(3)]),
+            Request::new(
+                "B".into(),
+                vec![
+                    Amount::with_quantity(None, Quantity::Units(vec![Range::num(2)])),
+                    Amount::with_quantity(Some("CTRL".into()), Quantity::Units(vec![Range::new(21, 22)])),
+                ],
+            ),
+        ];
+
+        let result = split(&inventory, &requests);
+
+        assert_eq!(
+            result,
+            Ok(Split {
+                filled_requests: vec![
+                    (
+                        "A".into(),
+                        vec![
+                            Group::new("EVAL".into(), vec![Range::num(1), Range::new(3, 4)]),
+                            Group::new("CTRL".into(), vec![Range::new(23, 25)]),
+                        ]
+                    ),
+                    (
+                        "B".into(),
+                        vec![
+                            Group::new("EVAL".into(), vec![Range::num(2)]),
+                            Group::new("CTRL".into(), vec![Range::new(21, 22)]),
+                        ]
+                    ),
+                ],
+                leftover_ranges: vec![
+                    Group::new("EVAL".into(), vec![Range::new(5, 10)]),
+                    Group::new("CTRL".into(), vec![Range::new(26, 30)]),
+                ],
+                shortfalls: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn bad_pinned_units() {
+        let inventory = vec![Group::new("EVAL".into(), vec![Range::new(1, 10)])];
+        let requests = vec![
+            Request::new(
+                "A".into(),
+                vec![Amount::with_quantity(None, Quantity::Units(vec![Range::new(8, 12)]))],
+            ),
+            Request::new(
+                "B".into(),
+                vec![Amount::with_quantity(None, Quantity::Units(vec![Range::new(1, 8)]))],
+            ),
+        ];
+
+        let result = split(&inventory, &requests);
+
+        assert_eq!(
+            result,
+            Err(vec![
+                SplitError::PinnedUnitsUnavailable {
+                    request_name: "A".into(),
+                    group_name: "EVAL".into(),
+                    units: Range::new(11, 12),
+                },
+                SplitError::PinnedTwice {
+                    first_request: "A".into(),
+                    second_request: "B".into(),
+                    group_name: "EVAL".into(),
+                    units: Range::num(8),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn serial_numbers() {
+        let inventory = crate::inventory::parse("A=LT123-0057-0072, LT124-0001").unwrap();
+        let requests = vec![Request::new("X".into(), vec![Amount::new(4)])];
+
+        let result = split(&inventory, &requests).unwrap();
+
+        assert_eq!(format!("{}", result.filled_requests[0].1[0]), "A=LT123-0057-0060");
+        assert_eq!(format!("{}", result.leftover_ranges[0]), "A=LT123-0061-0072, LT124-0001");
+    }
+
+    #[test]
+    fn largest_group() {
+        let inventory = vec![Group::new("A".into(), vec![Range::new(0, range::MAX_UNIT)])];
+        let requests = vec![
+            Request::new("X".into(), vec![Amount::with_quantity(None, Quantity::Fraction(1, 2))]),
+            Request::new("Y".into(), vec![Amount::with_quantity(None, Quantity::Rest)]),
+            Request::new("Z".into(), vec![Amount::new(1)]),
+        ];
+
+        let result = split(&inventory, &requests).unwrap();
+
+        let half = u64::MAX / 2;
+        assert_eq!(result.filled_requests[0].1[0].ranges(), &[Range::new(0, half - 1)]);
+        assert_eq!(result.filled_requests[1].1[0].ranges(), &[Range::new(half + 1, range::MAX_UNIT)]);
+        assert_eq!(result.filled_requests[2].1[0].ranges(), &[Range::num(half)]);
+        assert!(result.leftover_ranges[0].ranges().is_empty());
+    }
+
+    #[test]
+    fn more_than_largest_group() {
+        let inventory = vec![Group::new("A".into(), vec![Range::new(1, range::MAX_UNIT)])];
+        let requests = vec![Request::new("X".into(), vec![Amount::new(u64::MAX)])];
+
+        let result = split(&inventory, &requests);
+
+        assert_eq!(
+            result,
+            Err(vec![SplitError::NotEnough {
+                request_name: "X".into(),
+                group_name: "A".into(),
+                amount_needed: 1,
+            }])
+        );
+    }
+
+    #[test]
+    fn normalized_groups() {
+        let inventory = vec![Group::new(
+            "A".into(),
+            vec![Range::new(6, 10), Range::new(1, 5), Range::num(11)],
+        )];
+        let requests = vec![
+            Request::new("X".into(), vec![Amount::new(3)]),
+            Request::new(
+                "Y".into(),
+                vec![Amount::with_quantity(None, Quantity::Units(vec![Range::num(3), Range::new(1, 2)]))],
+            ),
+        ];
+
+        let result = split(&inventory, &requests).unwrap();
+        assert_eq!(result.filled_requests[1].1[0].ranges(), &[Range::new(1, 3)]);
+        assert_eq!(result.leftover_ranges[0].ranges(), &[Range::new(4, 5), Range::new(9, 11)]);
+
+        let options = SplitOptions {
+            normalize: false,
+            ..SplitOptions::default()
+        };
+        let result = split_with(&inventory, &requests, &options).unwrap();
+        assert_eq!(result.filled_requests[1].1[0].ranges(), &[Range::num(3), Range::new(1, 2)]);
+        assert_eq!(
+            result.leftover_ranges[0].ranges(),
+            &[Range::new(9, 10), Range::new(4, 5), Range::num(11)]
+        );
+    }
+
+    #[test]
+    fn return_units() {
+        let inventory = vec![
+            Group::new("A".into(), vec![Range::new(1, 20)]),
+            Group::new("B".into(), vec![Range::new(21, 30)]),
+        ];
+        let requests = vec![
+            Request::new("X".into(), vec![Amount::new(10), Amount::new(5)]),
+            Request::new("Y".into(), vec![Amount::new(5)]),
+        ];
+        let mut result = split(&inventory, &requests).unwrap();
+
+        let returned = result
+            .return_units("X", Some(&[Range::new(3, 4), Range::num(9), Range::num(22)]))
+            .unwrap();
+        assert_eq!(
+            returned,
+            vec![
+                Group::new("A".into(), vec![Range::new(3, 4), Range::num(9)]),
+                Group::new("B".into(), vec![Range::num(22)]),
+            ]
+        );
+        assert_eq!(
+            result.filled_requests[0].1,
+            vec![
+                Group::new("A".into(), vec![Range::new(1, 2), Range::new(5, 8), Range::num(10)]),
+                Group::new("B".into(), vec![Range::num(21), Range::new(23, 25)]),
+            ]
+        );
+        assert_eq!(
+            result.leftover_ranges,
+            vec![
+                Group::new("A".into(), vec![Range::new(3, 4), Range::num(9), Range::new(16, 20)]),
+                Group::new("B".into(), vec![Range::num(22), Range::new(26, 30)]),
+            ]
+        );
+
+        let returned = result.return_units("Y", None).unwrap();
+        assert_eq!(returned, vec![Group::new("A".into(), vec![Range::new(11, 15)])]);
+        assert_eq!(result.filled_requests[1].1, vec![Group::new("A".into(), vec![])]);
+        assert_eq!(
+            result.leftover_ranges[0],
+            Group::new("A".into(), vec![Range::new(3, 4), Range::num(9), Range::new(11, 20)])
+        );
+    }
+
+    #[test]
+    fn return_units_not_allocated() {
+        let inventory = vec![Group::new("A".into(), vec![Range::new(1, 20)])];
+        let requests = vec![
+            Request::new("X".into(), vec![Amount::new(10)]),
+            Request::new("Y".into(), vec![Amount::new(5)]),
+        ];
+        let mut result = split(&inventory, &requests).unwrap();
+        let before = split(&inventory, &requests).unwrap();
+
+        assert_eq!(
+            result.return_units("X", Some(&[Range::new(8, 12), Range::num(30)])),
+            Err(vec![
+                SplitError::UnitsNotAllocated {
+                    request_name: "X".into(),
+                    units: Range::new(11, 12),
+                },
+                SplitError::UnitsNotAllocated {
+                    request_name: "X".into(),
+                    units: Range::num(30),
+                },
+            ])
+        );
+        assert_eq!(
+            result.return_units("Z", None),
+            Err(vec![SplitError::UnknownRequest {
+                request_name: "Z".into(),
+            }])
+        );
+        assert_eq!(result, before);
+    }
+
+    #[test]
+    fn stepped_groups() {
+        let inventory = vec![Group::new("A".into(), vec![Range::new(1, 99).with_step(2)])];
+        let requests = vec![
+            Request::new("X".into(), vec![Amount::new(10)]),
+            Request::new(
+                "Y".into(),
+                vec![Amount::with_quantity(None, Quantity::Units(vec![Range::num(5)]))],
+            ),
+        ];
+
+        let result = split(&inventory, &requests).unwrap();
+        assert_eq!(
+            result.filled_requests[0].1[0].ranges(),
+            &[Range::new(1, 3).with_step(2), Range::new(7, 21).with_step(2)]
+        );
+        assert_eq!(format!("{}", result.leftover_ranges[0]), "A=23-99/2");
+
+        let options = SplitOptions {
+            allocator: &TakeFromEnd,
+            ..SplitOptions::default()
+        };
+        let result = split_with(&inventory, &requests, &options).unwrap();
+        assert_eq!(result.filled_requests[0].1[0].ranges(), &[Range::new(81, 99).with_step(2)]);
+        assert_eq!(
+            result.leftover_ranges[0].ranges(),
+            &[Range::new(1, 3).with_step(2), Range::new(7, 79).with_step(2)]
+        );
+    }
+
+    #[test]
+    fn units_left_between_stepped_exclusions() {
+        // Units are given out from the gaps between the excluded units in order
+        let inventory = crate::inventory::parse("A=1-30 !1-30/10").unwrap();
+        let requests = vec![Request::new("X".into(), vec![Amount::new(5)])];
+        let options = SplitOptions {
+            normalize: false,
+            ..SplitOptions::default()
+        };
+        let result = split_with(&inventory, &requests, &options).unwrap();
+        assert_eq!(result.filled_requests[0].1[0].ranges(), &[Range::new(2, 6)]);
+        assert_eq!(
+            result.leftover_ranges[0].ranges(),
+            &[Range::new(7, 10), Range::new(12, 20), Range::new(22, 30)]
+        );
+
+        let inventory = crate::inventory::parse("A=1-2000000 !1-2000000/20000").unwrap();
+        assert_eq!(inventory[0].ranges().len(), 100);
+        let requests = vec![Request::new("X".into(), vec![Amount::new(30_000)])];
+        let result = split(&inventory, &requests).unwrap();
+        assert_eq!(
+            result.filled_requests[0].1[0].ranges(),
+            &[Range::new(2, 20000), Range::new(20002, 30002)]
+        );
+    }
+
+    #[test]
+    fn reused_units() {
+        let inventory = vec![
+            Group::new("A".into(), vec![Range::new(1, 100)]),
+            Group::new("B".into(), vec![Range::new(1, 20)]),
+        ];
+        let requests = vec![
+            Request::new("TC".into(), vec![Amount::new(77), Amount::new(10)]),
+            Request::new("OTHER".into(), vec![Amount::new(5)]),
+            Request::same_as("HAST".into(), "TC".into()),
+            Request::new("BURN".into(), vec![Amount::new(40)]).with_source(Source::From("TC".into())),
+            Request::new("SHOCK".into(), vec![Amount::new(30), Amount::new(10)]).with_source(Source::From("TC".into())),
+            Request::new("RETEST".into(), vec![Amount::new(5)]).with_source(Source::From("BURN".into())),
+        ];
+        let result = split(&inventory, &requests).unwrap();
+        let tc = vec![
+            Group::new("A".into(), vec![Range::new(1, 77)]),
+            Group::new("B".into(), vec![Range::new(1, 10)]),
+        ];
+        assert_eq!(
+            result.filled_requests,
+            vec![
+                ("TC".into(), tc.clone()),
+                ("OTHER".into(), vec![Group::new("A".into(), vec![Range::new(78, 82)])]),
+                ("HAST".into(), tc),
+                ("BURN".into(), vec![Group::new("A".into(), vec![Range::new(1, 40)])]),
+                (
+                    "SHOCK".into(),
+                    vec![
+                        Group::new("A".into(), vec![Range::new(41, 70)]),
+                        Group::new("B".into(), vec![Range::new(1, 10)]),
+                    ]
+                ),
+                ("RETEST".into(), vec![Group::new("A".into(), vec![Range::new(1, 5)])]),
+            ]
+        );
+        assert_eq!(
+            result.leftover_ranges,
+            vec![
+                Group::new("A".into(), vec![Range::new(83, 100)]),
+                Group::new("B".into(), vec![Range::new(11, 20)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reused_units_errors() {
+        let inventory = vec![Group::new("A".into(), vec![Range::new(1, 20)])];
+        let requests = vec![
+            Request::new("X".into(), vec![Amount::new(5)]).with_source(Source::From("NOPE".into())),
+            Request::new("Y".into(), vec![Amount::new(1)]).with_source(Source::From("Z".into())),
+            Request::same_as("Z".into(), "Y".into()),
+        ];
+        assert_eq!(
+            split(&inventory, &requests),
+            Err(vec![
+                SplitError::UnknownSource {
+                    request_name: "X".into(),
+                    source_name: "NOPE".into(),
+                },
+                SplitError::CyclicSource {
+                    request_name: "Y".into(),
+                },
+                SplitError::CyclicSource {
+                    request_name: "Z".into(),
+                },
+            ])
+        );
+
+        let requests = vec![
+            Request::new("TC".into(), vec![Amount::new(10)]),
+            Request::new("HAST".into(), vec![Amount::new(11)]).with_source(Source::From("TC".into())),
+            Request::new("RETEST".into(), vec![Amount::new(1)]).with_source(Source::From("HAST".into())),
+        ];
+        assert_eq!(
+            split(&inventory, &requests),
+            Err(vec![SplitError::NotEnoughInSource {
+                request_name: "HAST".into(),
+                source_name: "TC".into(),
+                group_name: "A".into(),
+                amount_needed: 1,
+            }])
+        );
+        let options = SplitOptions {
+            partial: true,
+            ..SplitOptions::default()
+        };
+        assert_eq!(
+            split_with(&inventory, &requests, &options).unwrap().shortfalls,
+            vec![Shortfall {
+                request_name: "HAST".into(),
+                group_name: "A".into(),
+                requested: 11,
+                missing: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn return_reused_units() {
+        let inventory = vec![Group::new("A".into(), vec![Range::new(1, 20)])];
+        let requests = vec![
+            Request::new("TC".into(), vec![Amount::new(10)]),
+            Request::same_as("HAST".into(), "TC".into()),
+        ];
+        let mut result = split(&inventory, &requests).unwrap();
+
+        let returned = result.return_units("HAST", None).unwrap();
+        assert_eq!(returned, vec![Group::new("A".into(), vec![Range::new(1, 10)])]);
+        assert_eq!(result.leftover_ranges, vec![Group::new("A".into(), vec![Range::new(11, 20)])]);
+
+        result.return_units("TC", Some(&[Range::new(1, 5)])).unwrap();
+        assert_eq!(
+            result.leftover_ranges,
+            vec![Group::new("A".into(), vec![Range::new(1, 5), Range::new(11, 20)])]
+        );
+    }
+
+    #[test]
+    fn units_given_twice() {
+        let inventory = crate::inventory::parse("ALL=1-10, FAILED=3, GOOD = ALL - FAILED").unwrap();
+        let requests = vec![
+            Request::new("X".into(), vec![Amount::named("ALL".into(), 4)]),
+            Request::new("Y".into(), vec![Amount::named("GOOD".into(), 2)]),
+            Request::same_as("Z".into(), "Y".into()),
+        ];
+        let result = split(&inventory, &requests).unwrap();
+        assert_eq!(
+            result.units_given_twice(),
+            vec![GivenTwice {
+                first_group: "ALL".into(),
+                second_group: "GOOD".into(),
+                units: Range::new(1, 2).into(),
+            }]
+        );
+        assert_eq!(
+            result.units_given_twice()[0].to_string(),
+            "Units 1-2 were given out from both ALL and GOOD"
+        );
+
+        // Reused units are in the same group, so they are only given out once
+        let requests = vec![
+            Request::new("Y".into(), vec![Amount::named("GOOD".into(), 2)]),
+            Request::same_as("Z".into(), "Y".into()),
+        ];
+        assert_eq!(split(&inventory, &requests).unwrap().units_given_twice(), vec![]);
+    }
 }
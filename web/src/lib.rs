@@ -1,7 +1,8 @@
-use unit_splitter_core::group::{Group, Groups};
+use unit_splitter_core::group::{Group, Groups, Name};
 use unit_splitter_core::inventory::{self, InventoryParseResult};
-use unit_splitter_core::requests::{self, RequestsParseResult};
-use unit_splitter_core::split::{self, Split, SplitResult};
+use unit_splitter_core::pipeline::{self, Pipeline, PipelineParseError, Stage, StageErrors};
+use unit_splitter_core::requests::ParseOptions;
+use unit_splitter_core::split::{SplitOptions, Strategy};
 
 const TITLE: &'static str = "Unit Splitter";
 const AUTHORS: &'static str = env!("CARGO_PKG_AUTHORS");
@@ -15,14 +16,20 @@ pub struct Model {
     inventory_string: String,
     requests_string: String,
     inventory: InventoryParseResult,
-    requests: RequestsParseResult,
-    split: SplitResult,
+    stages: Result<Vec<Stage>, Vec<PipelineParseError>>,
+    strategy: Strategy,
+    partial: bool,
+    merge_duplicates: bool,
+    pipeline: Result<Pipeline, StageErrors>,
 }
 
 #[derive(Debug)]
 pub enum Msg {
     GotInventoryString(String),
     GotRequestString(String),
+    GotStrategy(String),
+    GotPartial(bool),
+    GotMergeDuplicates(bool),
 }
 
 impl Default for Model {
@@ -31,9 +38,12 @@ impl Default for Model {
             inventory_string: "".into(),
             requests_string: "".into(),
             inventory: inventory::parse(""),
-            requests: requests::parse(""),
-            split: Ok(unit_splitter_core::split::Split {
-                filled_requests: Vec::new(),
+            stages: pipeline::parse(""),
+            strategy: Strategy::default(),
+            partial: false,
+            merge_duplicates: false,
+            pipeline: Ok(Pipeline {
+                stages: Vec::new(),
                 leftover_ranges: Vec::new(),
             }),
         }
@@ -52,12 +62,27 @@ impl draco::App for Model {
             }
             GotRequestString(value) => {
                 self.requests_string = value;
-                self.requests = requests::parse(&self.requests_string);
+                self.parse_requests();
+            }
+            GotStrategy(value) => {
+                self.strategy = value.parse().unwrap_or_default();
+            }
+            GotPartial(value) => {
+                self.partial = value;
+            }
+            GotMergeDuplicates(value) => {
+                self.merge_duplicates = value;
+                self.parse_requests();
             }
         }
-        match (&self.inventory, &self.requests) {
-            (Ok(inventory), Ok(requests)) => {
-                self.split = split::split(&inventory, &requests);
+        match (&self.inventory, &self.stages) {
+            (Ok(inventory), Ok(stages)) => {
+                let options = SplitOptions {
+                    allocator: self.strategy.allocator(),
+                    partial: self.partial,
+                    ..SplitOptions::default()
+                };
+                self.pipeline = pipeline::split_with(&inventory, &stages, &options);
             }
             _ => {
                 // TODO: Make it apparent when output and input are desynchronized?
@@ -79,6 +104,13 @@ impl draco::App for Model {
 }
 
 impl Model {
+    fn parse_requests(&mut self) {
+        let options = ParseOptions {
+            merge_duplicates: self.merge_duplicates,
+        };
+        self.stages = pipeline::parse_with(&self.requests_string, &options);
+    }
+
     fn view_header(&self) -> draco::Node<Msg> {
         use draco::html as h;
         h::header().push(TITLE).into()
@@ -99,7 +131,7 @@ impl Model {
             .push(h::pre().push(inventory_errs_str));
 
         let mut requests_errs_str = String::new();
-        if let Err(ref errors) = self.requests {
+        if let Err(ref errors) = self.stages {
             for e in errors {
                 let _ = writeln!(requests_errs_str, "{}", e);
             }
@@ -121,39 +153,151 @@ impl Model {
             )
             .push(inventory_errs)
             .push(
-                h::div().class("requests-input").push(h::h1().push("Requests")).push(
-                    h::textarea()
-                        .class("input")
-                        .attr("placeholder", "enter requests")
-                        .attr("value", self.requests_string.clone())
-                        .on_input(Msg::GotRequestString),
-                ),
+                h::div()
+                    .class("requests-input")
+                    .push(h::h1().push("Requests"))
+                    .push(
+                        h::textarea()
+                            .class("input")
+                            .attr("placeholder", "enter requests")
+                            .attr("value", self.requests_string.clone())
+                            .on_input(Msg::GotRequestString),
+                    )
+                    .push(self.view_merge_duplicates()),
             )
             .push(requests_errs)
             .push(
                 h::div()
                     .class("output")
                     .push(h::h1().push("Output"))
-                    .push(self.view_output()),
+                    .push(self.view_strategy())
+                    .push(self.view_partial())
+                    .push(self.view_output())
+                    .push(self.view_shortfalls()),
+            )
+            .into()
+    }
+
+    fn view_strategy(&self) -> draco::Node<Msg> {
+        use draco::html as h;
+        let options = Strategy::ALL.iter().map(|strategy| {
+            let option = h::option()
+                .attr("value", strategy.name())
+                .push(strategy.name());
+            if *strategy == self.strategy {
+                option.attr("selected", "selected")
+            } else {
+                option
+            }
+        });
+        h::label()
+            .class("strategy")
+            .push("Strategy ")
+            .push(h::select().append(options).on_input(Msg::GotStrategy))
+            .into()
+    }
+
+    fn view_partial(&self) -> draco::Node<Msg> {
+        use draco::html as h;
+        h::label()
+            .class("partial")
+            .push(
+                h::input()
+                    .attr("type", "checkbox")
+                    .checked(self.partial)
+                    .on_checked(Msg::GotPartial),
             )
+            .push(" Fill what is available")
+            .into()
+    }
+
+    fn view_merge_duplicates(&self) -> draco::Node<Msg> {
+        use draco::html as h;
+        h::label()
+            .class("merge-duplicates")
+            .push(
+                h::input()
+                    .attr("type", "checkbox")
+                    .checked(self.merge_duplicates)
+                    .on_checked(Msg::GotMergeDuplicates),
+            )
+            .push(" Add up requests with the same name")
             .into()
     }
 
     fn view_output(&self) -> draco::Node<Msg> {
         use draco::html as h;
+        use std::fmt::Write;
         let div = h::div().class("output-grid");
-        match &self.split {
-            Ok(Split {
-                filled_requests,
+        match &self.pipeline {
+            Ok(Pipeline {
+                stages: splits,
                 leftover_ranges,
-            }) => div
-                .append(filled_requests.iter().map(view_filled_request))
-                .push(view_filled_request(&("Leftover Units", leftover_ranges)))
-                .into(),
-            Err(e) => div.push(format!("{}", e)).into(),
+            }) => {
+                let stages = self.stages.as_ref().map(|s| s.as_slice()).unwrap_or(&[]);
+                let mut div = div;
+                for (idx, (stage_name, split)) in splits.iter().enumerate() {
+                    if !stage_name.is_empty() {
+                        div = div.push(view_stage_name(stage_name));
+                    }
+                    // Filled requests are in the same order as the requests they came from
+                    let requests = stages.get(idx).map(Stage::requests).unwrap_or(&[]);
+                    let rows = split.filled_requests.iter().enumerate().map(|(request_idx, (name, groups))| {
+                        let comment = requests.get(request_idx).and_then(|r| r.comment());
+                        view_filled_request(name, groups, comment)
+                    });
+                    div = div.append(rows);
+                }
+                div.push(view_filled_request("Leftover Units", leftover_ranges, None))
+                    .into()
+            }
+            Err(stage_errors) => {
+                let mut errors_str = String::new();
+                if !stage_errors.stage.is_empty() {
+                    let _ = writeln!(errors_str, "In [{}]:", Name(&stage_errors.stage));
+                }
+                for e in &stage_errors.errors {
+                    let _ = writeln!(errors_str, "{}", e);
+                }
+                div.push(h::pre().push(errors_str)).into()
+            }
         }
     }
 
+    fn view_shortfalls(&self) -> draco::Node<Msg> {
+        use draco::html as h;
+        use std::fmt::Write;
+
+        let mut shortfalls_str = String::new();
+        if let Ok(ref pipeline) = self.pipeline {
+            for (stage_name, split) in pipeline.stages.iter().filter(|(_, split)| !split.shortfalls.is_empty()) {
+                if !stage_name.is_empty() {
+                    let _ = writeln!(shortfalls_str, "In [{}]:", Name(stage_name));
+                }
+                for shortfall in &split.shortfalls {
+                    let _ = writeln!(
+                        shortfalls_str,
+                        "{} is missing {} of {} units from {}",
+                        shortfall.request_name,
+                        shortfall.missing,
+                        shortfall.requested,
+                        shortfall.group_name
+                    );
+                }
+                for (group_name, missing) in split.missing_by_group() {
+                    let _ = writeln!(shortfalls_str, "Total missing from {}: {}", group_name, missing);
+                }
+            }
+            for twice in pipeline.units_given_twice() {
+                let _ = writeln!(shortfalls_str, "Warning: {}", twice);
+            }
+        }
+        h::div()
+            .class("shortfalls")
+            .push(h::pre().push(shortfalls_str))
+            .into()
+    }
+
     fn view_footer(&self) -> draco::Node<Msg> {
         use draco::html as h;
         h::footer()
@@ -169,21 +313,27 @@ impl Model {
     }
 }
 
-fn view_filled_request<S: AsRef<str>, I: AsRef<[Group]>>(
-    (request_name, inventory): &(S, I),
-) -> draco::Node<Msg> {
+fn view_stage_name(stage_name: &str) -> draco::Node<Msg> {
     use draco::html as h;
     h::div()
         .class("output-row")
-        .push(
-            h::div()
-                .class("output-request-name")
-                .push(request_name.as_ref()),
-        )
-        .push(
-            h::div()
-                .class("output-inventory")
-                .push(Groups(inventory.as_ref())),
-        )
+        .push(h::div().class("output-stage").push(format!("[{}]", Name(stage_name))))
+        .into()
+}
+
+fn view_filled_request(request_name: &str, inventory: &[Group], comment: Option<&str>) -> draco::Node<Msg> {
+    use draco::html as h;
+    let counts: Vec<String> = inventory.iter().map(|g| g.count().to_string()).collect();
+    let mut inventory_div = h::div()
+        .class("output-inventory")
+        .push(Groups(inventory))
+        .push(h::span().class("output-counts").push(counts.join(", ")));
+    if let Some(comment) = comment {
+        inventory_div = inventory_div.push(h::span().class("output-comment").push(comment));
+    }
+    h::div()
+        .class("output-row")
+        .push(h::div().class("output-request-name").push(request_name))
+        .push(inventory_div)
         .into()
 }